@@ -0,0 +1,708 @@
+//! Built-in opponent.
+//!
+//! The alpha-beta engine first looks for a win it can force with fours alone (threat-space
+//! search), then searches the most promising moves with alpha-beta, deepening till its time
+//! is up. Positions are scored by the lines of win_length each side can still complete, the
+//! fuller the better, while wins are only those the rule set counts. Levels trade strength
+//! for time through depth, width and time budget.
+//! The [`mcts`] engine plays out games instead and only takes the time budget of a level.
+//! Searches run on a [`BotPool`] shared by all rooms.
+use serde::{Deserialize, Serialize};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::gomoku_ol::{Board, PieceType};
+use crate::rules::{RuleSet, DIRECTIONS};
+
+mod mcts;
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 5;
+pub const DEFAULT_LEVEL: u8 = 3;
+
+/// score of a won position, less the plies it takes
+const WIN: i64 = 1 << 50;
+/// forced replies to fours are searched past the depth, but not beyond this
+const MAX_PLY: i64 = 40;
+/// how many times the score of a line grows with each stone in it
+const LINE_FACTOR: i64 = 8;
+const EMPTY: u8 = 0;
+/// cells within this many rows and columns of a stone are worth playing
+const NEAR: usize = 2;
+
+/// how a bot searches
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    /// threat-space search and alpha-beta
+    #[default]
+    AlphaBeta,
+    /// Monte-Carlo tree search
+    Mcts,
+}
+impl Engine {
+    /// nickname of a bot of the engine at level
+    pub fn nickname(self, level: u8) -> String {
+        match self {
+            Engine::AlphaBeta => format!("bot level {}", level),
+            Engine::Mcts => format!("mcts bot level {}", level),
+        }
+    }
+}
+
+/// threads bots search on, shared by every room so searches never hold up a room and
+/// never take more cores than the pool has
+#[derive(Clone)]
+pub struct BotPool {
+    pool: Arc<rayon::ThreadPool>,
+}
+impl BotPool {
+    /// a pool of threads threads, 0 for one per cpu
+    pub fn new(threads: usize) -> BotPool {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("bot-{}", i))
+            .build()
+            .expect("start bot threads failed");
+        BotPool {
+            pool: Arc::new(pool),
+        }
+    }
+
+    /// search position with engine at level on a thread of the pool, done gets the move.
+    /// A search that panics is logged and done gets None, the server goes on
+    pub fn search<F>(&self, position: Position, engine: Engine, level: u8, done: F)
+    where
+        F: FnOnce(Option<(usize, usize)>) + Send + 'static,
+    {
+        self.pool.spawn(move || {
+            let pos = panic::catch_unwind(AssertUnwindSafe(|| best_move(position, engine, level)))
+                .unwrap_or_else(|_| {
+                    eprintln!("{:?} bot search at level {} panicked", engine, level);
+                    None
+                });
+            done(pos);
+        });
+    }
+}
+
+/// what a bot sees of the game when it is its turn
+pub struct Position {
+    pub board: Board,
+    pub to_move: PieceType,
+    pub win_length: usize,
+    pub rule: RuleSet,
+}
+
+/// how hard the engine tries at a level
+struct Strength {
+    /// plies of alpha-beta
+    depth: u32,
+    /// moves searched at each node, best first
+    width: usize,
+    time: Duration,
+    /// fours in a row the threat-space search tries
+    vcf_depth: u32,
+}
+
+fn strength(level: u8) -> Strength {
+    let (depth, width, time_ms, vcf_depth) = match level {
+        1 => (1, 5, 150, 0),
+        2 => (2, 8, 300, 2),
+        3 => (4, 10, 800, 4),
+        4 => (6, 12, 1500, 8),
+        _ => (8, 15, 3000, 12),
+    };
+    Strength {
+        depth,
+        width,
+        time: Duration::from_millis(time_ms),
+        vcf_depth,
+    }
+}
+
+/// the move of the side to move by engine at level, None if there is no legal one
+pub fn best_move(mut position: Position, engine: Engine, level: u8) -> Option<(usize, usize)> {
+    let strength = strength(level);
+    let mut search = Search::new(&position, Instant::now() + strength.time);
+    let cols = search.cols;
+    let at = |cell: usize| (cell / cols, cell % cols);
+    let me = color(position.to_move);
+    let mut legal = |cell: usize| {
+        let Position {
+            board,
+            to_move,
+            rule,
+            ..
+        } = &mut position;
+        board.get(at(cell).0, at(cell).1) == PieceType::EMPTY
+            && rule.forbidden(board, at(cell), *to_move).is_none()
+    };
+
+    if search.cells.iter().all(|v| *v == EMPTY) {
+        return Some((search.rows / 2, cols / 2));
+    }
+    if let Some(cell) = search.four_cells(me).into_iter().find(|v| legal(*v)) {
+        return Some(at(cell));
+    }
+    // blocking is all that is left, if it does not help any legal move will do
+    let threats = search.four_cells(3 - me);
+    if let Some(cell) = threats.into_iter().find(|v| legal(*v)) {
+        return Some(at(cell));
+    }
+    let mut moves = match engine {
+        Engine::AlphaBeta => {
+            if let Some(cell) = search.vcf(me, strength.vcf_depth).filter(|v| legal(*v)) {
+                return Some(at(cell));
+            }
+            search.candidates(me, strength.width)
+        }
+        Engine::Mcts => search.candidates(me, mcts::WIDTH),
+    };
+    moves.retain(|v| legal(*v));
+    if moves.is_empty() {
+        moves = (0..search.cells.len()).filter(|v| legal(*v)).collect();
+    }
+    let first = *moves.first()?;
+    let best = match engine {
+        Engine::AlphaBeta => search.root(moves, me, &strength),
+        Engine::Mcts => mcts::search(&mut search, moves, me),
+    };
+    Some(at(best.unwrap_or(first)))
+}
+
+/// 1 for black, 2 for white
+fn color(piece_type: PieceType) -> u8 {
+    match piece_type {
+        PieceType::BLACK => 1,
+        PieceType::WHITE => 2,
+        PieceType::EMPTY => EMPTY,
+    }
+}
+
+/// the board as cells in a row plus every line of win_length on it, with how many stones
+/// of each color it holds
+struct Search {
+    rows: usize,
+    cols: usize,
+    win_length: usize,
+    rule: RuleSet,
+    cells: Vec<u8>,
+    /// first cell and stride of each line
+    lines: Vec<(usize, usize)>,
+    /// indices of the lines through each cell
+    lines_of: Vec<Vec<usize>>,
+    /// cells NEAR each cell, itself included
+    neighbors: Vec<Vec<usize>>,
+    /// stones NEAR each cell
+    near: Vec<usize>,
+    /// stones in each line by color
+    counts: Vec<[usize; 3]>,
+    /// score of a line holding that many stones of one color only
+    weights: Vec<i64>,
+    /// sum of the scores of all lines, from black's side
+    score: i64,
+    deadline: Instant,
+    nodes: u64,
+    timed_out: bool,
+}
+impl Search {
+    fn new(position: &Position, deadline: Instant) -> Search {
+        let (rows, cols) = (position.board.row_size(), position.board.col_size());
+        let k = position.win_length;
+        let mut lines = Vec::new();
+        for row in 0..rows {
+            for col in 0..cols {
+                let start = row * cols + col;
+                if col + k <= cols {
+                    lines.push((start, 1));
+                }
+                if row + k <= rows {
+                    lines.push((start, cols));
+                    if col + k <= cols {
+                        lines.push((start, cols + 1));
+                    }
+                    if col + 1 >= k {
+                        lines.push((start, cols - 1));
+                    }
+                }
+            }
+        }
+        let mut lines_of = vec![Vec::new(); rows * cols];
+        for (i, &(start, stride)) in lines.iter().enumerate() {
+            for step in 0..k {
+                lines_of[start + step * stride].push(i);
+            }
+        }
+        let neighbors = (0..rows * cols)
+            .map(|cell| {
+                let (row, col) = (cell / cols, cell % cols);
+                let near_rows = row.saturating_sub(NEAR)..(row + NEAR + 1).min(rows);
+                near_rows
+                    .flat_map(|r| {
+                        let near_cols = col.saturating_sub(NEAR)..(col + NEAR + 1).min(cols);
+                        near_cols.map(move |c| r * cols + c)
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut weights = vec![0];
+        for _ in 0..k {
+            weights.push(weights.last().unwrap().max(&1) * LINE_FACTOR);
+        }
+        let mut search = Search {
+            rows,
+            cols,
+            win_length: k,
+            rule: position.rule,
+            cells: vec![EMPTY; rows * cols],
+            counts: vec![[0; 3]; lines.len()],
+            lines,
+            lines_of,
+            neighbors,
+            near: vec![0; rows * cols],
+            weights,
+            score: 0,
+            deadline,
+            nodes: 0,
+            timed_out: false,
+        };
+        for (row_i, col_j) in position.board.positions() {
+            let stone = color(position.board.get(row_i, col_j));
+            if stone != EMPTY {
+                search.place(row_i * cols + col_j, stone);
+            }
+        }
+        search
+    }
+
+    fn line_score(&self, line: usize) -> i64 {
+        match self.counts[line] {
+            [_, black, 0] => self.weights[black],
+            [_, 0, white] => -self.weights[white],
+            _ => 0,
+        }
+    }
+
+    fn place(&mut self, cell: usize, stone: u8) {
+        self.cells[cell] = stone;
+        for i in 0..self.neighbors[cell].len() {
+            self.near[self.neighbors[cell][i]] += 1;
+        }
+        for i in 0..self.lines_of[cell].len() {
+            let line = self.lines_of[cell][i];
+            self.score -= self.line_score(line);
+            self.counts[line][stone as usize] += 1;
+            self.score += self.line_score(line);
+        }
+    }
+
+    fn remove(&mut self, cell: usize) {
+        let stone = self.cells[cell];
+        self.cells[cell] = EMPTY;
+        for i in 0..self.neighbors[cell].len() {
+            self.near[self.neighbors[cell][i]] -= 1;
+        }
+        for i in 0..self.lines_of[cell].len() {
+            let line = self.lines_of[cell][i];
+            self.score -= self.line_score(line);
+            self.counts[line][stone as usize] -= 1;
+            self.score += self.line_score(line);
+        }
+    }
+
+    fn line_cells(&self, line: usize) -> impl Iterator<Item = usize> {
+        let (start, stride) = self.lines[line];
+        (0..self.win_length).map(move |step| start + step * stride)
+    }
+
+    /// empty cells of lines holding stones of stone only, and missing short of them
+    fn cells_of_lines(&self, stone: u8, missing: usize) -> Vec<usize> {
+        let other = 3 - stone as usize;
+        let mut cells = Vec::new();
+        for line in 0..self.lines.len() {
+            let counts = self.counts[line];
+            if counts[stone as usize] + missing == self.win_length && counts[other] == 0 {
+                for cell in self.line_cells(line) {
+                    if self.cells[cell] == EMPTY && !cells.contains(&cell) {
+                        cells.push(cell);
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    /// cells where stone would win
+    fn four_cells(&self, stone: u8) -> Vec<usize> {
+        let mut cells = self.cells_of_lines(stone, 1);
+        cells.retain(|&cell| self.wins(cell, stone));
+        cells
+    }
+
+    /// whether stone on cell makes a row the rule set counts as a win, cell may hold stone
+    /// already
+    fn wins(&self, cell: usize, stone: u8) -> bool {
+        let (rows, cols) = (self.rows as i32, self.cols as i32);
+        let (row, col) = ((cell / self.cols) as i32, (cell % self.cols) as i32);
+        DIRECTIONS.iter().any(|&(row_step, col_step)| {
+            let mut len = 1;
+            // what is right past the row on either side, EMPTY at the edge
+            let mut ends = [EMPTY; 2];
+            for (end, sign) in ends.iter_mut().zip([-1, 1].iter()) {
+                let (mut r, mut c) = (row + sign * row_step, col + sign * col_step);
+                while (0..rows).contains(&r) && (0..cols).contains(&c) {
+                    let v = self.cells[(r * cols + c) as usize];
+                    if v != stone {
+                        *end = v;
+                        break;
+                    }
+                    len += 1;
+                    r += sign * row_step;
+                    c += sign * col_step;
+                }
+            }
+            match self.rule {
+                RuleSet::Standard => len == self.win_length,
+                RuleSet::Renju if stone == 1 => len == self.win_length,
+                RuleSet::Caro => len >= self.win_length && ends != [3 - stone; 2],
+                _ => len >= self.win_length,
+            }
+        })
+    }
+
+    /// the only moves that make sense for stone if any: completing a line or else
+    /// blocking the lines the opponent is about to complete
+    fn forced(&self, stone: u8) -> Option<Vec<usize>> {
+        let wins = self.four_cells(stone);
+        if !wins.is_empty() {
+            return Some(wins[..1].to_vec());
+        }
+        let threats = self.four_cells(3 - stone);
+        Some(threats).filter(|v| !v.is_empty())
+    }
+
+    /// whether the stone at cell wins
+    fn completes(&self, cell: usize) -> bool {
+        self.wins(cell, self.cells[cell])
+    }
+
+    /// how useful cell is to stone: the lines it extends plus the lines of the opponent
+    /// it spoils
+    fn value(&self, cell: usize, stone: u8) -> i64 {
+        let mine = stone as usize;
+        let theirs = 3 - mine;
+        self.lines_of[cell]
+            .iter()
+            .map(|&line| {
+                let counts = self.counts[line];
+                let attack = if counts[theirs] == 0 {
+                    self.weights[counts[mine] + 1] - self.weights[counts[mine]]
+                } else {
+                    0
+                };
+                let defense = if counts[mine] == 0 {
+                    self.weights[counts[theirs]]
+                } else {
+                    0
+                };
+                attack + defense
+            })
+            .sum()
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(256) && Instant::now() >= self.deadline {
+            self.timed_out = true;
+        }
+        self.timed_out
+    }
+
+    /// a move of stone starting a chain of fours that ends in two at once, at most depth
+    /// fours long. The opponent is assumed to have no four of its own
+    fn vcf(&mut self, stone: u8, depth: u32) -> Option<usize> {
+        if depth == 0 || self.out_of_time() {
+            return None;
+        }
+        for cell in self.cells_of_lines(stone, 2) {
+            self.place(cell, stone);
+            let fours = self.four_cells(stone);
+            let won = match fours[..] {
+                [] => false,
+                [block] => {
+                    self.place(block, 3 - stone);
+                    let won = self.four_cells(3 - stone).is_empty()
+                        && self.vcf(stone, depth - 1).is_some();
+                    self.remove(block);
+                    won
+                }
+                _ => true,
+            };
+            self.remove(cell);
+            if won {
+                return Some(cell);
+            }
+        }
+        None
+    }
+
+    /// empty cells near stones, the most useful to stone first, at most width of them
+    fn candidates(&self, stone: u8, width: usize) -> Vec<usize> {
+        let mut moves = (0..self.cells.len())
+            .filter(|&cell| self.near[cell] > 0 && self.cells[cell] == EMPTY)
+            .map(|cell| (cell, self.value(cell, stone)))
+            .collect::<Vec<(usize, i64)>>();
+        moves.sort_by_key(|v| -v.1);
+        moves.truncate(width);
+        moves.into_iter().map(|v| v.0).collect()
+    }
+
+    /// score for stone to move, searching depth plies and every forced reply to a four
+    fn negamax(
+        &mut self,
+        stone: u8,
+        depth: u32,
+        mut alpha: i64,
+        beta: i64,
+        ply: i64,
+        width: usize,
+    ) -> i64 {
+        if self.out_of_time() {
+            return 0;
+        }
+        if !self.four_cells(stone).is_empty() {
+            return WIN - ply;
+        }
+        let threats = self.four_cells(3 - stone);
+        let moves = match threats.len() {
+            0 if depth == 0 || ply >= MAX_PLY => {
+                let score = self.score;
+                return if stone == 1 { score } else { -score };
+            }
+            0 => self.candidates(stone, width),
+            1 => threats,
+            // two ways to complete a line can not both be blocked
+            _ => return -(WIN - ply - 1),
+        };
+        if moves.is_empty() {
+            return 0;
+        }
+        let mut best = -WIN;
+        for cell in moves {
+            self.place(cell, stone);
+            let score = -self.negamax(
+                3 - stone,
+                depth.saturating_sub(1),
+                -beta,
+                -alpha,
+                ply + 1,
+                width,
+            );
+            self.remove(cell);
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta || self.timed_out {
+                break;
+            }
+        }
+        best
+    }
+
+    /// best of moves for stone by iterative deepening, None if not even the first depth
+    /// got a score in time
+    fn root(&mut self, mut moves: Vec<usize>, stone: u8, strength: &Strength) -> Option<usize> {
+        let mut best = None;
+        for depth in 1..=strength.depth {
+            let mut alpha = -WIN - 1;
+            let mut depth_best = None;
+            for &cell in &moves {
+                self.place(cell, stone);
+                let score =
+                    -self.negamax(3 - stone, depth - 1, -WIN - 1, -alpha, 1, strength.width);
+                self.remove(cell);
+                if self.timed_out {
+                    break;
+                }
+                if score > alpha {
+                    alpha = score;
+                    depth_best = Some(cell);
+                }
+            }
+            if self.timed_out {
+                // a partial first depth is better than nothing
+                return best.or(depth_best);
+            }
+            best = depth_best;
+            if let Some(cell) = best {
+                // search the best move first next time, it prunes the most
+                moves.retain(|v| *v != cell);
+                moves.insert(0, cell);
+            }
+            if alpha.abs() >= WIN - MAX_PLY {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: [RuleSet; 4] = [
+        RuleSet::Freestyle,
+        RuleSet::Standard,
+        RuleSet::Caro,
+        RuleSet::Renju,
+    ];
+    const ENGINES: [Engine; 2] = [Engine::AlphaBeta, Engine::Mcts];
+    /// square and rectangular boards
+    const SIZES: [(usize, usize); 2] = [(15, 15), (11, 17)];
+
+    /// black to move on a board of size
+    fn position(
+        size: (usize, usize),
+        rule: RuleSet,
+        black: &[(usize, usize)],
+        white: &[(usize, usize)],
+    ) -> Position {
+        let mut board = Board::new(size.0, size.1);
+        for &(row_i, col_j) in black {
+            board.set(row_i, col_j, PieceType::BLACK);
+        }
+        for &(row_i, col_j) in white {
+            board.set(row_i, col_j, PieceType::WHITE);
+        }
+        Position {
+            board,
+            to_move: PieceType::BLACK,
+            win_length: 5,
+            rule,
+        }
+    }
+
+    /// the move of engine at the lowest level in every position, checked to be legal
+    fn legal_moves<F>(make: F) -> Vec<(Position, (usize, usize))>
+    where
+        F: Fn((usize, usize), RuleSet) -> Position,
+    {
+        let mut moves = Vec::new();
+        for &size in SIZES.iter() {
+            for &rule in RULES.iter() {
+                for &engine in ENGINES.iter() {
+                    let mut board = make(size, rule).board;
+                    let pos = best_move(make(size, rule), engine, MIN_LEVEL).unwrap();
+                    let context = format!("{:?} {:?} on {:?}: {:?}", engine, rule, size, pos);
+                    assert_eq!(board.get(pos.0, pos.1), PieceType::EMPTY, "{}", context);
+                    let forbidden = rule.forbidden(&mut board, pos, PieceType::BLACK);
+                    assert!(forbidden.is_none(), "{} is {:?}", context, forbidden);
+                    moves.push((make(size, rule), pos));
+                }
+            }
+        }
+        moves
+    }
+
+    /// whether the side to move wins with pos
+    fn wins(position: &Position, pos: (usize, usize)) -> bool {
+        let mut board = position.board.clone();
+        board.set(pos.0, pos.1, position.to_move);
+        position
+            .rule
+            .winning_line(&board, pos, position.win_length)
+            .is_some()
+    }
+
+    #[test]
+    fn takes_an_immediate_win() {
+        let moves = legal_moves(|size, rule| {
+            position(
+                size,
+                rule,
+                &[(2, 3), (2, 4), (2, 5), (2, 6)],
+                &[(8, 3), (8, 4), (8, 5)],
+            )
+        });
+        for (position, pos) in moves {
+            assert!(wins(&position, pos), "{:?} does not win", pos);
+        }
+    }
+
+    #[test]
+    fn takes_only_wins_the_rule_counts() {
+        // an overline at (1, 4), a five blocked at both ends at (4, 5) and real fives at
+        // (8, 3) and (8, 8)
+        let moves = legal_moves(|size, rule| {
+            position(
+                size,
+                rule,
+                &[
+                    (1, 0),
+                    (1, 1),
+                    (1, 2),
+                    (1, 3),
+                    (1, 5),
+                    (4, 1),
+                    (4, 2),
+                    (4, 3),
+                    (4, 4),
+                    (8, 4),
+                    (8, 5),
+                    (8, 6),
+                    (8, 7),
+                ],
+                &[(4, 0), (4, 6), (10, 0), (10, 2)],
+            )
+        });
+        for (position, pos) in moves {
+            assert!(
+                wins(&position, pos),
+                "{:?} does not win in {:?}",
+                pos,
+                position.rule
+            );
+        }
+    }
+
+    #[test]
+    fn blocks_a_four() {
+        let moves = legal_moves(|size, rule| {
+            position(
+                size,
+                rule,
+                &[(5, 0), (8, 8), (9, 9), (10, 4)],
+                &[(5, 1), (5, 2), (5, 3), (5, 4)],
+            )
+        });
+        for (_, pos) in moves {
+            assert_eq!(pos, (5, 5));
+        }
+    }
+
+    #[test]
+    fn skips_a_forbidden_overline() {
+        legal_moves(|size, rule| {
+            position(
+                size,
+                rule,
+                &[(7, 0), (7, 1), (7, 2), (7, 3), (7, 5)],
+                &[(3, 8), (3, 10), (5, 12), (9, 14)],
+            )
+        });
+    }
+
+    #[test]
+    fn skips_a_forbidden_double_three() {
+        legal_moves(|size, rule| {
+            position(
+                size,
+                rule,
+                &[(7, 5), (7, 6), (5, 7), (6, 7)],
+                &[(6, 6), (8, 8), (9, 3), (3, 9)],
+            )
+        });
+    }
+}
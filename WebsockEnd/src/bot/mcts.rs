@@ -0,0 +1,184 @@
+//! Monte-Carlo tree search engine.
+//!
+//! UCT: every playout walks down the tree picking the child with the best win rate plus a
+//! bonus for having been tried little, adds one node and plays the game out from there.
+//! Playouts are biased by the patterns of the alpha-beta engine: wins are taken, fours are
+//! blocked and otherwise the most useful of a few random moves near the stones is played.
+//! Which moves a node tries is narrowed down the same way.
+use rand::Rng;
+use std::time::Instant;
+
+use super::{Search, EMPTY};
+
+/// moves a node tries, most useful first
+pub const WIDTH: usize = 12;
+/// weight of the bonus for moves tried little against the win rate
+const EXPLORATION: f64 = 1.0;
+/// random moves a playout compares to pick the one it plays
+const SAMPLES: usize = 4;
+/// random cells a playout looks at to find one sample near the stones
+const SAMPLE_TRIES: usize = 40;
+
+struct Node {
+    /// the move leading here
+    cell: usize,
+    /// color that played cell
+    stone: u8,
+    children: Vec<usize>,
+    /// moves of the opponent of stone not added as children yet, the next one last
+    untried: Vec<usize>,
+    visits: u32,
+    /// playouts through the node won by stone, draws count half
+    wins: f64,
+    /// winner if the game is over after cell, EMPTY for a draw
+    result: Option<u8>,
+}
+
+/// the most played of moves for stone by the time search runs out of time, None if there
+/// was no time for a single playout
+pub fn search(search: &mut Search, mut moves: Vec<usize>, stone: u8) -> Option<usize> {
+    let mut rng = rand::thread_rng();
+    moves.reverse();
+    let mut nodes = vec![Node {
+        cell: usize::MAX,
+        stone: 3 - stone,
+        children: Vec::new(),
+        untried: moves,
+        visits: 0,
+        wins: 0.0,
+        result: None,
+    }];
+    let mut placed = Vec::new();
+    while Instant::now() < search.deadline {
+        let mut path = vec![0];
+        // select
+        let mut node = 0;
+        while nodes[node].result.is_none()
+            && nodes[node].untried.is_empty()
+            && !nodes[node].children.is_empty()
+        {
+            node = select(&nodes, node);
+            search.place(nodes[node].cell, nodes[node].stone);
+            placed.push(nodes[node].cell);
+            path.push(node);
+        }
+        // expand
+        if let Some(cell) = nodes[node].untried.pop() {
+            let stone = 3 - nodes[node].stone;
+            search.place(cell, stone);
+            placed.push(cell);
+            let result = if search.completes(cell) {
+                Some(stone)
+            } else if search.cells.iter().all(|v| *v != EMPTY) {
+                Some(EMPTY)
+            } else {
+                None
+            };
+            let mut untried = match result {
+                Some(_) => Vec::new(),
+                None => search
+                    .forced(3 - stone)
+                    .unwrap_or_else(|| search.candidates(3 - stone, WIDTH)),
+            };
+            untried.reverse();
+            nodes.push(Node {
+                cell,
+                stone,
+                children: Vec::new(),
+                untried,
+                visits: 0,
+                wins: 0.0,
+                result,
+            });
+            let child = nodes.len() - 1;
+            nodes[node].children.push(child);
+            node = child;
+            path.push(node);
+        }
+        // play out
+        let winner = match nodes[node].result {
+            Some(winner) => winner,
+            None => playout(search, 3 - nodes[node].stone, &mut rng),
+        };
+        // back up
+        for &i in &path {
+            nodes[i].visits += 1;
+            nodes[i].wins += match winner {
+                EMPTY => 0.5,
+                v if v == nodes[i].stone => 1.0,
+                _ => 0.0,
+            };
+        }
+        while let Some(cell) = placed.pop() {
+            search.remove(cell);
+        }
+    }
+    nodes[0]
+        .children
+        .iter()
+        .max_by_key(|&&v| nodes[v].visits)
+        .map(|&v| nodes[v].cell)
+}
+
+/// the child of node with the best upper confidence bound, every child has been visited
+fn select(nodes: &[Node], node: usize) -> usize {
+    let log_visits = (nodes[node].visits as f64).ln();
+    let bound = |child: &Node| {
+        let visits = child.visits as f64;
+        child.wins / visits + EXPLORATION * (log_visits / visits).sqrt()
+    };
+    *nodes[node]
+        .children
+        .iter()
+        .max_by(|a, b| bound(&nodes[**a]).total_cmp(&bound(&nodes[**b])))
+        .unwrap()
+}
+
+/// play the game out with stone to move, return the winner or EMPTY for a draw.
+/// The board is left as it was
+fn playout<R: Rng>(search: &mut Search, mut stone: u8, rng: &mut R) -> u8 {
+    let mut played = Vec::new();
+    let winner = loop {
+        if !search.four_cells(stone).is_empty() {
+            break stone;
+        }
+        let cell = match search.four_cells(3 - stone).first() {
+            Some(&cell) => cell,
+            None => match sample(search, stone, rng) {
+                Some(cell) => cell,
+                None => break EMPTY,
+            },
+        };
+        search.place(cell, stone);
+        played.push(cell);
+        stone = 3 - stone;
+    };
+    while let Some(cell) = played.pop() {
+        search.remove(cell);
+    }
+    winner
+}
+
+/// the most useful to stone of a few random empty cells near the stones, any empty cell
+/// if none is found that way, None on a full board
+fn sample<R: Rng>(search: &Search, stone: u8, rng: &mut R) -> Option<usize> {
+    let cells = search.cells.len();
+    let mut best = None;
+    let mut found = 0;
+    for _ in 0..SAMPLE_TRIES {
+        let cell = rng.gen_range(0..cells);
+        if search.cells[cell] != EMPTY || search.near[cell] == 0 {
+            continue;
+        }
+        let value = search.value(cell, stone);
+        if best.is_none_or(|(_, v)| value > v) {
+            best = Some((cell, value));
+        }
+        found += 1;
+        if found == SAMPLES {
+            break;
+        }
+    }
+    best.map(|v| v.0)
+        .or_else(|| (0..cells).find(|&v| search.cells[v] == EMPTY))
+}
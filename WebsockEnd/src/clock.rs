@@ -0,0 +1,377 @@
+//! Game clocks.
+//!
+//! Each side has main time plus either a Fischer increment, added after each of its moves,
+//! or byo-yomi: once main time is used up every move has to be made within a period, each
+//! overrun uses up one period and running out of them loses. Only the clock of the side to
+//! act runs, from the moment both seats are taken, through the opening and the game.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::gomoku_ol::PieceType;
+use crate::protocol::{ClockView, ClocksView};
+
+pub const MAX_MAIN_SECS: u64 = 3 * 60 * 60;
+pub const MAX_INCREMENT_SECS: u64 = 60;
+pub const MAX_PERIODS: u32 = 10;
+pub const MAX_PERIOD_SECS: u64 = 5 * 60;
+
+/// time control of a room, chosen at `create_room`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct TimeControl {
+    pub main_secs: u64,
+    /// added after each move while there is main time left
+    #[serde(default)]
+    pub increment_secs: u64,
+    /// number of byo-yomi periods
+    #[serde(default)]
+    pub periods: u32,
+    #[serde(default)]
+    pub period_secs: u64,
+}
+impl TimeControl {
+    /// reject values out of the limits and mixes that make no sense
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.main_secs > MAX_MAIN_SECS
+            || self.increment_secs > MAX_INCREMENT_SECS
+            || self.periods > MAX_PERIODS
+            || self.period_secs > MAX_PERIOD_SECS
+        {
+            return Err(Error::bad_request(&format!(
+                "time control is limited to {}s main time, {}s increment and {} periods of {}s",
+                MAX_MAIN_SECS, MAX_INCREMENT_SECS, MAX_PERIODS, MAX_PERIOD_SECS
+            )));
+        }
+        if (self.periods == 0) != (self.period_secs == 0) {
+            return Err(Error::bad_request("periods and period_secs go together"));
+        }
+        if self.increment_secs > 0 && self.periods > 0 {
+            return Err(Error::bad_request("use either an increment or byo-yomi"));
+        }
+        if self.main_secs == 0 && self.periods == 0 {
+            return Err(Error::bad_request("a clock needs main time or byo-yomi"));
+        }
+        Ok(())
+    }
+
+    fn period(&self) -> Duration {
+        Duration::from_secs(self.period_secs)
+    }
+}
+
+/// time left of one side
+#[derive(Clone, Copy)]
+struct Clock {
+    main: Duration,
+    periods: u32,
+}
+impl Clock {
+    /// a clock that ran out
+    const OUT: Clock = Clock {
+        main: Duration::ZERO,
+        periods: 0,
+    };
+
+    fn new(time_control: &TimeControl) -> Clock {
+        Clock {
+            main: Duration::from_secs(time_control.main_secs),
+            periods: time_control.periods,
+        }
+    }
+
+    /// the clock after running for elapsed, with the time left of the current period once in
+    /// byo-yomi. None if it ran out
+    fn after(self, elapsed: Duration, time_control: &TimeControl) -> Option<(Clock, Duration)> {
+        if elapsed < self.main {
+            let main = self.main - elapsed;
+            let clock = Clock {
+                main,
+                periods: self.periods,
+            };
+            return Some((clock, main));
+        }
+        let period = time_control.period().as_millis();
+        let over = (elapsed - self.main).as_millis();
+        if period == 0 || over / period >= self.periods as u128 {
+            return None;
+        }
+        let clock = Clock {
+            main: Duration::ZERO,
+            periods: self.periods - (over / period) as u32,
+        };
+        Some((
+            clock,
+            Duration::from_millis((period - over % period) as u64),
+        ))
+    }
+
+    /// time till the clock runs out
+    fn total(&self, time_control: &TimeControl) -> Duration {
+        self.main + time_control.period() * self.periods
+    }
+}
+
+/// clocks of both sides
+pub struct Clocks {
+    time_control: TimeControl,
+    black: Clock,
+    white: Clock,
+    /// side whose clock runs and since when
+    running: Option<(PieceType, Instant)>,
+}
+impl Clocks {
+    pub fn new(time_control: TimeControl) -> Clocks {
+        Clocks {
+            time_control,
+            black: Clock::new(&time_control),
+            white: Clock::new(&time_control),
+            running: None,
+        }
+    }
+    pub fn time_control(&self) -> TimeControl {
+        self.time_control
+    }
+    /// full time for both sides, nothing running
+    pub fn reset(&mut self) {
+        *self = Clocks::new(self.time_control);
+    }
+
+    fn clock(&self, piece_type: PieceType) -> Clock {
+        match piece_type {
+            PieceType::BLACK => self.black,
+            _ => self.white,
+        }
+    }
+    fn clock_mut(&mut self, piece_type: PieceType) -> &mut Clock {
+        match piece_type {
+            PieceType::BLACK => &mut self.black,
+            _ => &mut self.white,
+        }
+    }
+
+    /// when the running clock runs out
+    pub fn deadline(&self) -> Option<Instant> {
+        let (piece_type, since) = self.running?;
+        Some(since + self.clock(piece_type).total(&self.time_control))
+    }
+
+    /// the side whose clock has run out by now
+    pub fn flagged(&self, now: Instant) -> Option<PieceType> {
+        match (self.running, self.deadline()) {
+            (Some((piece_type, _)), Some(deadline)) if now >= deadline => Some(piece_type),
+            _ => None,
+        }
+    }
+
+    /// stop the running clock, taking the time it ran off its side. Return that side
+    fn charge(&mut self, now: Instant) -> Option<PieceType> {
+        let (piece_type, since) = self.running.take()?;
+        let time_control = self.time_control;
+        let clock = self.clock_mut(piece_type);
+        *clock = clock
+            .after(now.saturating_duration_since(since), &time_control)
+            .map_or(Clock::OUT, |v| v.0);
+        Some(piece_type)
+    }
+
+    /// start the clock of piece_type unless one runs already
+    pub fn start(&mut self, piece_type: PieceType, now: Instant) {
+        if self.running.is_none() {
+            self.running = Some((piece_type, now));
+        }
+    }
+
+    /// the side to move moved: charge it, add its increment and start the clock of next
+    pub fn press(&mut self, next: PieceType, now: Instant) {
+        if let Some(piece_type) = self.charge(now) {
+            let increment = Duration::from_secs(self.time_control.increment_secs);
+            let clock = self.clock_mut(piece_type);
+            if !clock.main.is_zero() {
+                clock.main += increment;
+            }
+        }
+        self.running = Some((next, now));
+    }
+
+    /// run the clock of piece_type instead of the running one, without any increment
+    pub fn switch(&mut self, piece_type: PieceType, now: Instant) {
+        if self.charge(now).is_some() {
+            self.running = Some((piece_type, now));
+        }
+    }
+
+    pub fn stop(&mut self, now: Instant) {
+        self.charge(now);
+    }
+
+    /// time left of both sides at now
+    pub fn view(&self, now: Instant) -> ClocksView {
+        let view = |piece_type: PieceType| {
+            let clock = self.clock(piece_type);
+            let (clock, left) = match self.running {
+                Some((running, since)) if running == piece_type => clock
+                    .after(now.saturating_duration_since(since), &self.time_control)
+                    .unwrap_or((Clock::OUT, Duration::ZERO)),
+                _ if clock.main.is_zero() => (clock, self.time_control.period()),
+                _ => (clock, clock.main),
+            };
+            ClockView {
+                remaining_ms: left.as_millis() as u64,
+                byoyomi: clock.main.is_zero(),
+                periods: clock.periods,
+            }
+        };
+        ClocksView {
+            black: view(PieceType::BLACK),
+            white: view(PieceType::WHITE),
+            running: self.running.map_or(PieceType::EMPTY, |v| v.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(v: u64) -> Duration {
+        Duration::from_secs(v)
+    }
+
+    fn byoyomi() -> TimeControl {
+        TimeControl {
+            main_secs: 10,
+            increment_secs: 0,
+            periods: 3,
+            period_secs: 5,
+        }
+    }
+
+    fn fischer() -> TimeControl {
+        TimeControl {
+            main_secs: 10,
+            increment_secs: 2,
+            periods: 0,
+            period_secs: 0,
+        }
+    }
+
+    #[test]
+    fn after_runs_main_time_first() {
+        let (clock, left) = Clock::new(&byoyomi()).after(secs(3), &byoyomi()).unwrap();
+        assert_eq!((clock.main, clock.periods, left), (secs(7), 3, secs(7)));
+    }
+
+    #[test]
+    fn after_rolls_over_periods() {
+        let clock = Clock::new(&byoyomi());
+        let (rolled, left) = clock.after(secs(10), &byoyomi()).unwrap();
+        assert_eq!((rolled.main, rolled.periods, left), (secs(0), 3, secs(5)));
+        let (rolled, left) = clock.after(secs(17), &byoyomi()).unwrap();
+        assert_eq!((rolled.periods, left), (2, secs(3)));
+        let (rolled, left) = clock.after(secs(24), &byoyomi()).unwrap();
+        assert_eq!((rolled.periods, left), (1, secs(1)));
+        assert!(clock.after(secs(25), &byoyomi()).is_none());
+    }
+
+    #[test]
+    fn after_without_byoyomi_runs_out_with_main_time() {
+        assert!(Clock::new(&fischer()).after(secs(10), &fischer()).is_none());
+    }
+
+    #[test]
+    fn start_charges_the_first_move() {
+        let start = Instant::now();
+        let mut clocks = Clocks::new(fischer());
+        clocks.start(PieceType::BLACK, start);
+        assert_eq!(clocks.deadline(), Some(start + secs(10)));
+        assert_eq!(clocks.flagged(start + secs(10)), Some(PieceType::BLACK));
+        clocks.press(PieceType::WHITE, start + secs(4));
+        let view = clocks.view(start + secs(4));
+        assert_eq!(view.black.remaining_ms, 8_000);
+        assert_eq!(view.running, PieceType::WHITE);
+    }
+
+    #[test]
+    fn start_keeps_the_running_clock() {
+        let start = Instant::now();
+        let mut clocks = Clocks::new(fischer());
+        clocks.start(PieceType::BLACK, start);
+        clocks.start(PieceType::WHITE, start + secs(3));
+        assert_eq!(clocks.view(start + secs(3)).running, PieceType::BLACK);
+        assert_eq!(clocks.deadline(), Some(start + secs(10)));
+    }
+
+    #[test]
+    fn press_charges_the_mover_and_adds_the_increment() {
+        let start = Instant::now();
+        let mut clocks = Clocks::new(fischer());
+        // nothing runs before the first press
+        clocks.press(PieceType::WHITE, start);
+        clocks.press(PieceType::BLACK, start + secs(3));
+        let view = clocks.view(start + secs(4));
+        assert_eq!(view.white.remaining_ms, 9_000);
+        assert_eq!(view.black.remaining_ms, 9_000);
+        assert_eq!(view.running, PieceType::BLACK);
+        assert_eq!(clocks.deadline(), Some(start + secs(13)));
+        assert_eq!(clocks.flagged(start + secs(12)), None);
+        assert_eq!(clocks.flagged(start + secs(13)), Some(PieceType::BLACK));
+    }
+
+    #[test]
+    fn press_adds_no_increment_in_byoyomi() {
+        let time_control = TimeControl {
+            periods: 2,
+            period_secs: 5,
+            ..fischer()
+        };
+        let start = Instant::now();
+        let mut clocks = Clocks::new(time_control);
+        clocks.press(PieceType::WHITE, start);
+        clocks.press(PieceType::BLACK, start + secs(12));
+        let white = clocks.view(start + secs(12)).white;
+        assert!(white.byoyomi);
+        assert_eq!((white.remaining_ms, white.periods), (5_000, 2));
+    }
+
+    #[test]
+    fn press_keeps_periods_used_in_byoyomi() {
+        let start = Instant::now();
+        let mut clocks = Clocks::new(byoyomi());
+        clocks.press(PieceType::WHITE, start);
+        clocks.press(PieceType::BLACK, start + secs(16));
+        let white = clocks.view(start + secs(20)).white;
+        assert_eq!((white.remaining_ms, white.periods), (5_000, 2));
+        let black = clocks.view(start + secs(20)).black;
+        assert!(!black.byoyomi);
+        assert_eq!(black.remaining_ms, 6_000);
+    }
+
+    #[test]
+    fn switch_charges_without_increment() {
+        let start = Instant::now();
+        let mut clocks = Clocks::new(fischer());
+        clocks.press(PieceType::WHITE, start);
+        clocks.switch(PieceType::BLACK, start + secs(4));
+        let view = clocks.view(start + secs(4));
+        assert_eq!(view.white.remaining_ms, 6_000);
+        assert_eq!(view.running, PieceType::BLACK);
+    }
+
+    #[test]
+    fn switch_does_not_start_a_stopped_clock() {
+        let start = Instant::now();
+        let mut clocks = Clocks::new(fischer());
+        clocks.switch(PieceType::BLACK, start);
+        assert_eq!(clocks.view(start).running, PieceType::EMPTY);
+        assert_eq!(clocks.deadline(), None);
+    }
+
+    #[test]
+    fn view_shows_a_flagged_clock_as_empty() {
+        let start = Instant::now();
+        let mut clocks = Clocks::new(byoyomi());
+        clocks.press(PieceType::WHITE, start);
+        let white = clocks.view(start + secs(30)).white;
+        assert_eq!((white.remaining_ms, white.periods), (0, 0));
+    }
+}
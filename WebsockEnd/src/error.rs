@@ -0,0 +1,82 @@
+//! Errors returned to clients.
+use serde::Serialize;
+
+use crate::gomoku_ol::MoveError;
+
+/// closed set of error codes a client can get
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// malformed message or invalid arguments
+    BadRequest,
+    UnknownCommand,
+    NoSuchRoom,
+    NoSuchPlayer,
+    NotInRoom,
+    /// the player is not allowed to do this, e.g. observers putting pieces
+    Forbidden,
+    /// move or takeback against the rules, `reason` of the error tells which one
+    IllegalMove,
+    RateLimited,
+    /// the session token of `resume` is unknown or expired
+    InvalidToken,
+    /// another player online or an account has the nickname
+    NicknameTaken,
+    /// no account with the username and password of `login`
+    LoginFailed,
+    Internal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Error {
+    pub code: ErrorCode,
+    /// human readable description
+    pub message: String,
+    /// detail of illegal_move, e.g. `occupied`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+}
+impl Error {
+    pub fn new(code: ErrorCode, message: &str) -> Error {
+        Error {
+            code,
+            message: message.to_string(),
+            reason: None,
+        }
+    }
+    pub fn bad_request(message: &str) -> Error {
+        Error::new(ErrorCode::BadRequest, message)
+    }
+    pub fn no_such_room(room_id: i32) -> Error {
+        Error::new(
+            ErrorCode::NoSuchRoom,
+            &format!("room {} does not exist", room_id),
+        )
+    }
+    pub fn no_such_player(player_id: i32) -> Error {
+        Error::new(
+            ErrorCode::NoSuchPlayer,
+            &format!("player {} is not online", player_id),
+        )
+    }
+    pub fn login_failed() -> Error {
+        Error::new(ErrorCode::LoginFailed, "wrong username or password")
+    }
+    pub fn internal() -> Error {
+        Error::new(ErrorCode::Internal, "internal server error")
+    }
+}
+impl From<MoveError> for Error {
+    fn from(err: MoveError) -> Error {
+        let code = match err {
+            MoveError::NotAPlayer => ErrorCode::Forbidden,
+            MoveError::NotInRoom => ErrorCode::NotInRoom,
+            _ => ErrorCode::IllegalMove,
+        };
+        Error {
+            code,
+            message: err.message().to_string(),
+            reason: Some(err.as_str()),
+        }
+    }
+}
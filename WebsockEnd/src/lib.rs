@@ -1,15 +1,141 @@
+pub mod bot;
+pub mod clock;
+pub mod error;
+pub mod lobby;
+pub mod opening;
+pub mod protocol;
+pub mod rating;
+pub mod room;
+pub mod rules;
+pub mod store;
+
+/// sender to the connection task of a player, what is sent is written to its websocket
+pub type ClientSender = tokio::sync::mpsc::UnboundedSender<String>;
+/// closes the connection task of a player, once another connection resumed as it
+pub type ClientCloser = tokio::sync::mpsc::UnboundedSender<()>;
+/// sender to the connection task of a player of the rooms the lobby seated it in, with their ids
+pub type RoomSink = tokio::sync::mpsc::UnboundedSender<(i32, room::RoomHandle)>;
+/// where a lobby or room task sends the result of a request
+pub type Reply = tokio::sync::oneshot::Sender<Result<serde_json::Value, error::Error>>;
+
 pub mod gomoku_ol {
-    use std::{
-        collections::{HashMap, HashSet},
-        usize,
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::{HashMap, HashSet};
+    use std::time::{Duration, Instant};
+
+    use crate::bot::Position;
+    use crate::clock::Clocks;
+    use crate::error::{Error, ErrorCode};
+    use crate::lobby::Resumed;
+    use crate::opening::{Actor, Opening, OpeningPhase};
+    use crate::protocol::{
+        ClocksView, Event, GameOver, GameSettings, GameState, ListedRoom, OpeningView, Profile,
+        RoomInfo, RoomState, RoomView,
     };
+    use crate::rating::Rating;
+    use crate::room::RoomHandle;
+    use crate::rules::RuleSet;
+    use crate::store::Store;
+    use crate::{ClientCloser, ClientSender, RoomSink};
+
+    pub const DEFAULT_BOARD_SIZE: usize = 15;
+    pub const MIN_BOARD_SIZE: usize = 5;
+    pub const MAX_BOARD_SIZE: usize = 25;
+    pub const DEFAULT_WIN_LENGTH: usize = 5;
+    pub const MIN_WIN_LENGTH: usize = 3;
+    pub const MAX_WIN_LENGTH: usize = 10;
+    /// length of session tokens
+    const TOKEN_LEN: usize = 32;
+    pub const MAX_NICKNAME_LEN: usize = 16;
+    /// rating difference the queue accepts between two players who just joined it
+    const MATCH_WINDOW: f64 = 100.0;
+    /// the window of a player widens by MATCH_WINDOW_STEP every MATCH_WINDOW_STEP_SECS it waits
+    const MATCH_WINDOW_STEP: f64 = 50.0;
+    const MATCH_WINDOW_STEP_SECS: u64 = 5;
+    const MAX_MATCH_WINDOW: f64 = 1000.0;
+    pub const DEFAULT_LEADERBOARD_LIMIT: u32 = 20;
+    pub const MAX_LEADERBOARD_LIMIT: u32 = 100;
+    /// players are called guest<id> till they set a nickname
+    const GUEST_PREFIX: &str = "guest";
+    /// player id of the bot of a room, ids of players start at 1
+    pub const BOT_ID: i32 = 0;
 
     struct Player {
-        _id: i32,
+        id: i32,
         _ip_addr: String,
+        /// secret to `resume` as this player from another connection
+        token: String,
+        nickname: String,
+        /// account the player logged in to, guests have none
+        account: Option<i64>,
+        stats: Stats,
+        /// only kept for players with an account
+        rating: Rating,
+        sender: ClientSender,
+        closer: ClientCloser,
+        /// when the player is forgotten, set while its connection is gone
+        away_until: Option<Instant>,
+    }
+
+    /// results of the finished games of a player, aborted games do not count
+    #[derive(Clone, Copy, Default, Serialize)]
+    pub struct Stats {
+        pub games: u32,
+        pub wins: u32,
+        pub losses: u32,
+        pub draws: u32,
+    }
+    impl Stats {
+        fn add(&mut self, status: GameStatus, color: PieceType) {
+            match (status, color) {
+                (GameStatus::BlackWon, PieceType::BLACK)
+                | (GameStatus::WhiteWon, PieceType::WHITE) => self.wins += 1,
+                (GameStatus::BlackWon, _) | (GameStatus::WhiteWon, _) => self.losses += 1,
+                (GameStatus::Draw, _) => self.draws += 1,
+                _ => return,
+            }
+            self.games += 1;
+        }
     }
 
+    /// a seated player as the game started, it is rated as it was then
     #[derive(Clone, Copy)]
+    struct Seat {
+        player_id: i32,
+        /// guests have none
+        account: Option<i64>,
+        rating: Rating,
+    }
+
+    /// a finished game, reported by its room to the lobby
+    pub struct GameRecord {
+        pub room_id: i32,
+        /// id of the player who had black
+        pub black: i32,
+        pub white: i32,
+        pub status: GameStatus,
+        pub reason: EndReason,
+        pub moves: usize,
+        /// the room was created for rated games
+        pub rated: bool,
+    }
+    impl GameRecord {
+        /// 1 for a win of color, 0.5 for a draw and 0 for a loss
+        pub fn score(&self, color: PieceType) -> Option<f64> {
+            match (self.status, color) {
+                (GameStatus::BlackWon, PieceType::BLACK)
+                | (GameStatus::WhiteWon, PieceType::WHITE) => Some(1.0),
+                (GameStatus::BlackWon, _) | (GameStatus::WhiteWon, _) => Some(0.0),
+                (GameStatus::Draw, _) => Some(0.5),
+                _ => None,
+            }
+        }
+    }
+
+    #[allow(clippy::upper_case_acronyms)]
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
     pub enum PieceType {
         EMPTY,
         BLACK,
@@ -23,15 +149,174 @@ pub mod gomoku_ol {
                 _ => PieceType::EMPTY,
             }
         }
-        pub fn to_i32(&self) -> i32 {
+        pub fn to_i32(self) -> i32 {
             match self {
                 PieceType::EMPTY => -1,
                 PieceType::BLACK => 0,
                 PieceType::WHITE => 1,
             }
         }
+        /// color of the index-th move, black moves first
+        fn of_move(index: usize) -> PieceType {
+            if index.is_multiple_of(2) {
+                PieceType::BLACK
+            } else {
+                PieceType::WHITE
+            }
+        }
+    }
+    /// piece types go over the wire as their i32 value
+    impl Serialize for PieceType {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_i32(self.to_i32())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for PieceType {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<PieceType, D::Error> {
+            match i32::deserialize(deserializer)? {
+                v @ -1..=1 => Ok(PieceType::from_i32(v)),
+                v => Err(de::Error::custom(format!("invalid piece type {}", v))),
+            }
+        }
+    }
+    /// status of a game as clients see it
+    #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum GameStatus {
+        /// a seat is still free
+        Waiting,
+        InProgress,
+        BlackWon,
+        WhiteWon,
+        Draw,
+        Aborted,
+    }
+
+    /// why a game ended
+    #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum EndReason {
+        /// a winning line was made
+        Five,
+        Resign,
+        DrawAgreed,
+        BoardFull,
+        /// a player left the room in the middle of the game
+        PlayerLeft,
+        /// the clock of the loser ran out
+        Timeout,
+        /// the loser lost connection and did not come back in time
+        Abandoned,
+    }
+
+    /// how a game ended
+    #[derive(Clone)]
+    struct GameResult {
+        status: GameStatus,
+        reason: EndReason,
+        /// the winning line if the reason is Five
+        line: Vec<(usize, usize)>,
+    }
+
+    /// a piece put on board
+    #[derive(Clone, Copy)]
+    pub struct Move {
+        pub row_i: usize,
+        pub col_j: usize,
+        pub piece_type: PieceType,
+    }
+
+    /// reason of rejecting a move
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum MoveError {
+        NotYourTurn,
+        Occupied,
+        OutOfBounds,
+        GameOver,
+        /// observers can only watch
+        NotAPlayer,
+        NotInRoom,
+        /// the player has no piece on board to take back
+        NothingToUndo,
+        UndoPending,
+        NoUndoRequest,
+        DrawOfferPending,
+        NoDrawOffer,
+        ResetPending,
+        /// the other seat is still free
+        WaitingForOpponent,
+        /// forbidden for black in renju
+        Overline,
+        DoubleFour,
+        DoubleThree,
+        /// normal moves wait till the opening is done
+        OpeningInProgress,
+        /// the opening command does not fit the phase of the opening
+        WrongPhase,
+        WrongStoneCount,
+        BadFifthCount,
+        DuplicatePoint,
+        NotAProposal,
+    }
+    impl MoveError {
+        pub fn as_str(self) -> &'static str {
+            match self {
+                MoveError::NotYourTurn => "not_your_turn",
+                MoveError::Occupied => "occupied",
+                MoveError::OutOfBounds => "out_of_bounds",
+                MoveError::GameOver => "game_over",
+                MoveError::NotAPlayer => "not_a_player",
+                MoveError::NotInRoom => "not_in_room",
+                MoveError::NothingToUndo => "nothing_to_undo",
+                MoveError::UndoPending => "undo_pending",
+                MoveError::NoUndoRequest => "no_undo_request",
+                MoveError::DrawOfferPending => "draw_offer_pending",
+                MoveError::NoDrawOffer => "no_draw_offer",
+                MoveError::ResetPending => "reset_pending",
+                MoveError::WaitingForOpponent => "waiting_for_opponent",
+                MoveError::Overline => "overline",
+                MoveError::DoubleFour => "double_four",
+                MoveError::DoubleThree => "double_three",
+                MoveError::OpeningInProgress => "opening_in_progress",
+                MoveError::WrongPhase => "wrong_phase",
+                MoveError::WrongStoneCount => "wrong_stone_count",
+                MoveError::BadFifthCount => "bad_fifth_count",
+                MoveError::DuplicatePoint => "duplicate_point",
+                MoveError::NotAProposal => "not_a_proposal",
+            }
+        }
+        pub fn message(self) -> &'static str {
+            match self {
+                MoveError::NotYourTurn => "it is not your turn",
+                MoveError::Occupied => "there is already a piece there",
+                MoveError::OutOfBounds => "the position is outside the board",
+                MoveError::GameOver => "the game is over",
+                MoveError::NotAPlayer => "observers can not play",
+                MoveError::NotInRoom => "you are not in this room",
+                MoveError::NothingToUndo => "you have no move to take back",
+                MoveError::UndoPending => "a takeback is already requested",
+                MoveError::NoUndoRequest => "there is no takeback request to answer",
+                MoveError::DrawOfferPending => "a draw is already offered",
+                MoveError::NoDrawOffer => "there is no draw offer to accept",
+                MoveError::ResetPending => "a reset is already requested",
+                MoveError::WaitingForOpponent => "wait for an opponent to take the other seat",
+                MoveError::Overline => "black can not make six or more in a row",
+                MoveError::DoubleFour => "black can not make two fours at once",
+                MoveError::DoubleThree => "black can not make two open threes at once",
+                MoveError::OpeningInProgress => "the opening is not finished yet",
+                MoveError::WrongPhase => "this can not be done in the current phase of the opening",
+                MoveError::WrongStoneCount => {
+                    "wrong number of stones for this phase of the opening"
+                }
+                MoveError::BadFifthCount => "the number of 5th moves must be between 1 and 8",
+                MoveError::DuplicatePoint => "the same point is given twice",
+                MoveError::NotAProposal => "the point is not one of the proposed 5th moves",
+            }
+        }
     }
 
+    #[allow(clippy::upper_case_acronyms)]
     #[derive(Clone, Copy, Eq, PartialEq)]
     enum PlayerType {
         OBSERVER,
@@ -39,258 +324,2333 @@ pub mod gomoku_ol {
         PLAYER2,
     }
     impl PlayerType {
-        pub fn to_i32(&self) -> i32 {
+        pub fn to_i32(self) -> i32 {
             match self {
                 PlayerType::PLAYER1 => 0,
                 PlayerType::PLAYER2 => 1,
-                PlayerType::OBSERVER => -1
+                PlayerType::OBSERVER => -1,
             }
         }
     }
 
+    /// a player waiting in the matchmaking queue
+    struct QueueEntry {
+        player_id: i32,
+        /// only players asking for the same settings are paired
+        settings: GameSettings,
+        since: Instant,
+        rooms: RoomSink,
+    }
+    impl QueueEntry {
+        /// rating difference the player accepts at now
+        fn window(&self, now: Instant) -> f64 {
+            let steps =
+                now.saturating_duration_since(self.since).as_secs() / MATCH_WINDOW_STEP_SECS;
+            (MATCH_WINDOW + MATCH_WINDOW_STEP * steps as f64).min(MAX_MATCH_WINDOW)
+        }
+    }
+
+    /// two players paired by the queue and seated in a new room, its task is not started yet
+    pub struct Match {
+        pub room: Room,
+        /// id, connection and room sink of both players
+        pub players: Vec<(i32, ClientSender, RoomSink)>,
+    }
+
+    /// the lobby: connected players and the directory of rooms, each room runs in its own task
     pub struct Context {
-        rooms: HashMap<i32, Room>,
+        rooms: HashMap<i32, RoomEntry>,
         players: HashMap<i32, Player>,
         player_id_cnt: i32,
         room_id_cnt: i32,
+        /// seq of the latest lobby event
+        lobby_event_seq: u64,
+        store: Store,
+        /// matchmaking queue in the order players joined it
+        queue: Vec<QueueEntry>,
+        /// seated players of the game going on in each room, as it started
+        games: HashMap<i32, Vec<Seat>>,
+    }
+    /// a room as the lobby knows it, info is updated by the room after each change
+    struct RoomEntry {
+        handle: RoomHandle,
+        info: RoomInfo,
+        /// created by the queue for one game, it is not listed and dropped once the game is
+        /// over or the room is empty. Its task ends when the players let go of it too
+        matched: bool,
     }
     impl Context {
-        pub fn new() -> Context {
+        pub fn new(store: Store) -> Context {
             Context {
                 rooms: HashMap::new(),
                 players: HashMap::new(),
                 player_id_cnt: 0,
                 room_id_cnt: 0,
+                lobby_event_seq: 0,
+                store,
+                queue: Vec::new(),
+                games: HashMap::new(),
             }
         }
 
-        pub fn add_player(&mut self, ip_addr: &String) -> i32 {
+        /// add a player, return its id and session token
+        pub fn add_player(
+            &mut self,
+            ip_addr: &str,
+            sender: ClientSender,
+            closer: ClientCloser,
+        ) -> (i32, String) {
+            self.remove_expired();
             self.player_id_cnt += 1;
             let id = self.player_id_cnt;
+            let token = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(TOKEN_LEN)
+                .map(char::from)
+                .collect::<String>();
             self.players.insert(
                 id,
                 Player {
-                    _id: id,
-                    _ip_addr: ip_addr.clone(),
+                    id,
+                    _ip_addr: ip_addr.to_string(),
+                    token: token.clone(),
+                    nickname: format!("{}{}", GUEST_PREFIX, id),
+                    account: None,
+                    stats: Stats::default(),
+                    rating: Rating::default(),
+                    sender,
+                    closer,
+                    away_until: None,
                 },
             );
-            id
+            (id, token)
+        }
+
+        /// forget the players whose grace period ran out, so their names are free again
+        fn remove_expired(&mut self) {
+            let now = Instant::now();
+            self.players
+                .retain(|_, v| v.away_until.is_none_or(|until| until > now));
         }
 
         pub fn remove_player(&mut self, player_id: i32) {
             self.players.remove(&player_id);
-            self.rooms.iter_mut().for_each(|v| {
-                v.1.remove_player(player_id);
+            self.queue.retain(|v| v.player_id != player_id);
+        }
+
+        /// the connection behind sender dropped, keep the player for grace_period so it can
+        /// `resume`. Nothing happens if the player has resumed on another connection already
+        pub fn disconnect(
+            &mut self,
+            player_id: i32,
+            sender: &ClientSender,
+            grace_period: Duration,
+        ) {
+            match self.players.get_mut(&player_id) {
+                Some(player) if player.sender.same_channel(sender) => {
+                    self.queue.retain(|v| v.player_id != player_id);
+                    if grace_period.is_zero() {
+                        self.players.remove(&player_id);
+                    } else {
+                        player.away_until = Some(Instant::now() + grace_period);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        pub fn nickname(&self, player_id: i32) -> Result<String, Error> {
+            self.players
+                .get(&player_id)
+                .map(|v| v.nickname.clone())
+                .ok_or_else(|| Error::no_such_player(player_id))
+        }
+
+        /// nickname trimmed, if it is one a player can choose
+        fn check_nickname(nickname: &str) -> Result<&str, Error> {
+            let nickname = nickname.trim();
+            let len = nickname.chars().count();
+            if len == 0 || len > MAX_NICKNAME_LEN || nickname.chars().any(char::is_control) {
+                return Err(Error::bad_request(&format!(
+                    "nickname must be 1 to {} characters",
+                    MAX_NICKNAME_LEN
+                )));
+            }
+            if nickname
+                .to_lowercase()
+                .strip_prefix(GUEST_PREFIX)
+                .is_some_and(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_digit()))
+            {
+                return Err(Error::bad_request(&format!(
+                    "nicknames like {}1 are kept for guests",
+                    GUEST_PREFIX
+                )));
+            }
+            Ok(nickname)
+        }
+
+        /// fail if a player other than player_id goes by nickname
+        fn check_nickname_free(&self, player_id: i32, nickname: &str) -> Result<(), Error> {
+            let lowercase = nickname.to_lowercase();
+            if self
+                .players
+                .values()
+                .any(|v| v.id != player_id && v.nickname.to_lowercase() == lowercase)
+            {
+                return Err(Error::new(
+                    ErrorCode::NicknameTaken,
+                    &format!("nickname {} is taken", nickname),
+                ));
+            }
+            Ok(())
+        }
+
+        /// the player if it is not logged in, forbidden with message otherwise
+        fn guest_mut(&mut self, player_id: i32, message: &str) -> Result<&mut Player, Error> {
+            let player = self
+                .players
+                .get_mut(&player_id)
+                .ok_or_else(|| Error::no_such_player(player_id))?;
+            if player.account.is_some() {
+                return Err(Error::new(ErrorCode::Forbidden, message));
+            }
+            Ok(player)
+        }
+
+        /// change the nickname of the player, return it as it is stored.
+        /// Usernames of accounts can not be taken by guests
+        pub fn set_nickname(&mut self, player_id: i32, nickname: &str) -> Result<String, Error> {
+            let nickname = Context::check_nickname(nickname)?;
+            self.remove_expired();
+            self.check_nickname_free(player_id, nickname)?;
+            if self.store.find_account(nickname)?.is_some() {
+                return Err(Error::new(
+                    ErrorCode::NicknameTaken,
+                    &format!("nickname {} is the username of an account", nickname),
+                ));
+            }
+            let player = self.guest_mut(player_id, "logged in players go by their username")?;
+            player.nickname = nickname.to_string();
+            Ok(player.nickname.clone())
+        }
+
+        /// create an account and log the player in to it, return its username
+        pub fn register(
+            &mut self,
+            player_id: i32,
+            username: &str,
+            password_hash: &str,
+        ) -> Result<String, Error> {
+            let username = Context::check_nickname(username)?;
+            self.remove_expired();
+            self.check_nickname_free(player_id, username)?;
+            self.guest_mut(player_id, "already logged in")?;
+            let account_id = self.store.create_account(username, password_hash)?;
+            self.login(player_id, account_id)
+        }
+
+        /// id and password hash of the account called username
+        pub fn find_account(&self, username: &str) -> Result<(i64, String), Error> {
+            self.store
+                .find_account(username.trim())?
+                .map(|v| (v.id, v.password_hash))
+                .ok_or_else(Error::login_failed)
+        }
+
+        /// the player becomes the owner of the account, its username and stats.
+        /// Return the username
+        pub fn login(&mut self, player_id: i32, account_id: i64) -> Result<String, Error> {
+            self.remove_expired();
+            if self.players.values().any(|v| v.account == Some(account_id)) {
+                return Err(Error::new(
+                    ErrorCode::Forbidden,
+                    "the account is in use, `resume` to get it back",
+                ));
+            }
+            let account = self
+                .store
+                .account(account_id)?
+                .ok_or_else(Error::login_failed)?;
+            // the guest name of the player does not count
+            self.check_nickname_free(player_id, &account.username)?;
+            let player = self.guest_mut(player_id, "already logged in")?;
+            player.account = Some(account.id);
+            player.nickname = account.username;
+            player.stats = account.stats;
+            player.rating = account.rating;
+            Ok(player.nickname.clone())
+        }
+
+        pub fn profile(&self, player_id: i32) -> Result<Profile, Error> {
+            let player = self
+                .players
+                .get(&player_id)
+                .ok_or_else(|| Error::no_such_player(player_id))?;
+            Ok(Profile {
+                player_id,
+                nickname: player.nickname.clone(),
+                registered: player.account.is_some(),
+                rating: player.account.map(|_| player.rating.view()),
+                stats: player.stats,
+            })
+        }
+
+        /// wait in the queue for an opponent asking for the same settings, joining again
+        /// replaces the settings. The room both are seated in is sent to rooms
+        pub fn join_queue(
+            &mut self,
+            player_id: i32,
+            settings: GameSettings,
+            rooms: RoomSink,
+        ) -> Result<(), Error> {
+            if !self.players.contains_key(&player_id) {
+                return Err(Error::no_such_player(player_id));
+            }
+            Game::new(&settings)?;
+            self.queue.retain(|v| v.player_id != player_id);
+            self.queue.push(QueueEntry {
+                player_id,
+                settings,
+                since: Instant::now(),
+                rooms,
             });
+            Ok(())
         }
 
-        pub fn create_room(&mut self, name: String) -> String {
-            self.room_id_cnt += 1;
-            let id = self.room_id_cnt;
-            self.rooms.insert(id, Room::new(id, name.clone()));
-            format!("{{\"room\":{{\"id\":{},\"name\":\"{}\"}}}}", id, name)
+        pub fn leave_queue(&mut self, player_id: i32) -> Result<(), Error> {
+            let len = self.queue.len();
+            self.queue.retain(|v| v.player_id != player_id);
+            if self.queue.len() == len {
+                return Err(Error::bad_request("not in the queue"));
+            }
+            Ok(())
+        }
+
+        /// rating the queue pairs the player by, guests count as new accounts
+        fn rating_of(&self, player_id: i32) -> f64 {
+            self.players
+                .get(&player_id)
+                .map_or(Rating::default(), |v| v.rating)
+                .rating
         }
 
-        pub fn room_list_json(&self) -> Option<String> {
-            Some(format!(
-                "[{}]",
-                self.rooms
+        /// pair players of the queue, earliest first, each with the closest rating in the
+        /// windows of both. Paired players leave the queue seated in a new room
+        pub fn take_matches(&mut self, now: Instant) -> Vec<Match> {
+            let mut matches = Vec::new();
+            let mut i = 0;
+            while i < self.queue.len() {
+                let entry = &self.queue[i];
+                let rating = self.rating_of(entry.player_id);
+                let opponent = self
+                    .queue
                     .iter()
-                    .map(|v| -> String { v.1.to_json() })
-                    .collect::<Vec<String>>()
-                    .join(",")
-            ))
-        }
-
-        pub fn player_enter_room(&mut self, player_id: i32, room_id: i32) -> Option<String> {
-            if self.players.contains_key(&player_id) {
-                return match self.rooms.get_mut(&room_id) {
-                    Some(room) => {
-                        room.add_player(player_id);
-                        Some(format!("{}", room.to_json()))
+                    .enumerate()
+                    .skip(i + 1)
+                    .filter(|(_, v)| v.settings == entry.settings)
+                    .map(|(j, v)| (j, (self.rating_of(v.player_id) - rating).abs(), v))
+                    .filter(|(_, diff, v)| *diff <= entry.window(now).min(v.window(now)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|v| v.0);
+                match opponent {
+                    Some(j) => {
+                        let opponent = self.queue.remove(j);
+                        let entry = self.queue.remove(i);
+                        matches.extend(self.seat(entry, opponent));
                     }
-                    None => None,
-                };
+                    None => i += 1,
+                }
             }
-            None
+            matches
         }
 
-        pub fn player_exit_room(&mut self, player_id: i32, room_id: i32) -> Option<String> {
-            if self.players.contains_key(&player_id) {
-                return match self.rooms.get_mut(&room_id) {
-                    Some(room) => {
-                        room.remove_player(player_id);
-                        Some("{}".to_string())
+        /// a room for the settings of a with a and b seated, in random order
+        fn seat(&mut self, a: QueueEntry, b: QueueEntry) -> Option<Match> {
+            let mut entries = vec![a, b];
+            if rand::thread_rng().gen() {
+                entries.swap(0, 1);
+            }
+            let nicknames = entries
+                .iter()
+                .map(|v| self.nickname(v.player_id).ok())
+                .collect::<Option<Vec<String>>>();
+            let nicknames = match nicknames {
+                Some(v) => v,
+                None => {
+                    // the one still here keeps its place in the queue
+                    for entry in entries {
+                        if self.players.contains_key(&entry.player_id) {
+                            let at = self.queue.partition_point(|v| v.since <= entry.since);
+                            self.queue.insert(at, entry);
+                        }
                     }
-                    None => None,
-                };
+                    return None;
+                }
+            };
+            let name = format!("{} vs {}", nicknames[0], nicknames[1]);
+            // the settings were checked when a joined
+            let mut room = self.new_room(name, &entries[0].settings).ok()?;
+            let mut players = Vec::new();
+            for (entry, nickname) in entries.iter().zip(nicknames) {
+                room.enter(entry.player_id, nickname);
+                let sender = self.players.get(&entry.player_id)?.sender.clone();
+                players.push((entry.player_id, sender, entry.rooms.clone()));
             }
-            None
+            Some(Match { room, players })
         }
 
-        pub fn reset_game(&mut self, room_id: i32) -> Option<String> {
-            match self.rooms.get_mut(&room_id) {
-                Some(room) => {
-                    room.game.reset();
-                    Some("{}".to_string())
+        /// the game in room started with players seated, remember who they are now
+        pub fn game_started(&mut self, room_id: i32, players: &[i32]) {
+            let seats = players.iter().map(|&v| self.seat_of(v)).collect();
+            self.games.insert(room_id, seats);
+        }
+
+        /// the player as it is now, players who are gone count as guests
+        fn seat_of(&self, player_id: i32) -> Seat {
+            let player = self.players.get(&player_id);
+            Seat {
+                player_id,
+                account: player.and_then(|v| v.account),
+                rating: player.map_or(Rating::default(), |v| v.rating),
+            }
+        }
+
+        /// count the result of a finished game for both players, rate it if it is rated and
+        /// both have an account, save it for those with one and archive the game if any of
+        /// them has one. Players are counted as they were when the game started, even if they
+        /// left meanwhile
+        pub fn game_finished(&mut self, record: &GameRecord) {
+            let seats = self.games.remove(&record.room_id).unwrap_or_default();
+            let seat = |player_id: i32| {
+                seats
+                    .iter()
+                    .find(|v| v.player_id == player_id)
+                    .copied()
+                    .unwrap_or_else(|| self.seat_of(player_id))
+            };
+            let (black, white) = (seat(record.black), seat(record.white));
+            let accounts = (black.account, white.account);
+            // ratings before the game, None if it does not change them
+            let ratings = match accounts {
+                (Some(_), Some(_)) if record.rated => Some((black.rating, white.rating)),
+                _ => None,
+            };
+            for &(seat, color) in [(black, PieceType::BLACK), (white, PieceType::WHITE)].iter() {
+                // a player who logged in or out since the start is not the one who played
+                let player = self
+                    .players
+                    .get_mut(&seat.player_id)
+                    .filter(|v| v.account == seat.account);
+                let (mut stats, mut rating) = match (player.as_ref(), seat.account) {
+                    (Some(player), _) => (player.stats, player.rating),
+                    // gone already, the game still counts for its account
+                    (None, Some(account_id)) => match self.store.account(account_id) {
+                        Ok(Some(account)) => (account.stats, account.rating),
+                        _ => continue,
+                    },
+                    (None, None) => continue,
+                };
+                stats.add(record.status, color);
+                if let (Some((black, white)), Some(score)) = (ratings, record.score(color)) {
+                    let opponent = if color == PieceType::BLACK {
+                        white
+                    } else {
+                        black
+                    };
+                    rating = rating.update(&opponent, score);
+                }
+                if let Some(player) = player {
+                    player.stats = stats;
+                    player.rating = rating;
+                }
+                if let Some(account_id) = seat.account {
+                    // errors are logged by the store, the game is over anyway
+                    self.store.save_account(account_id, &stats, &rating).ok();
                 }
-                None => None,
+            }
+            if accounts.0.is_some() || accounts.1.is_some() {
+                self.store
+                    .add_game(record, accounts.0, accounts.1, ratings.is_some())
+                    .ok();
             }
         }
 
-        pub fn put_piece(
+        /// send the events of the player the token belongs to to sender from now on,
+        /// return its id and the rooms it is in. A connection still serving the player is
+        /// closed through its closer, closer takes its place
+        pub fn resume(
             &mut self,
-            room_id: i32,
-            row_i: usize,
-            col_j: usize,
-            piece_type: PieceType,
-        ) -> Option<String> {
-            match piece_type {
-                PieceType::EMPTY => None,
-                _ => match self.rooms.get_mut(&room_id) {
-                    Some(room) => {
-                        if row_i < room.game.row_size && col_j < room.game.col_size {
-                            room.game.put_piece(row_i, col_j, piece_type);
-                            return Some("{}".to_string());
-                        }
-                        return None;
-                    }
-                    None => None,
-                },
+            token: &str,
+            sender: ClientSender,
+            closer: ClientCloser,
+        ) -> Result<Resumed, Error> {
+            let now = Instant::now();
+            let player = self
+                .players
+                .values_mut()
+                .find(|v| v.token == token && v.away_until.is_none_or(|until| until > now))
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorCode::InvalidToken,
+                        "the session token is unknown or expired",
+                    )
+                })?;
+            if !player.sender.same_channel(&sender) {
+                // ignore failure, the connection is gone already
+                player.closer.send(()).unwrap_or_default();
             }
+            player.sender = sender;
+            player.closer = closer;
+            player.away_until = None;
+            let player_id = player.id;
+            let mut rooms = self
+                .rooms
+                .values()
+                .filter(|v| {
+                    v.info.game_players.contains_key(&player_id)
+                        || v.info.game_observers.contains(&player_id)
+                })
+                .map(|v| (v.info.id, v.handle.clone()))
+                .collect::<Vec<(i32, RoomHandle)>>();
+            rooms.sort_by_key(|v| v.0);
+            Ok((player_id, rooms))
         }
 
-        pub fn players_of_room(&self, room_id: i32) -> Option<Vec<i32>> {
-            match self.rooms.get(&room_id) {
-                Some(room) => Some(room.all_players()),
-                None => None,
+        /// handle of room to talk to it
+        pub fn room(&self, room_id: i32) -> Result<RoomHandle, Error> {
+            self.rooms
+                .get(&room_id)
+                .map(|v| v.handle.clone())
+                .ok_or_else(|| Error::no_such_room(room_id))
+        }
+
+        /// a new room with the next id, it is not listed till add_room
+        pub fn new_room(&mut self, name: String, settings: &GameSettings) -> Result<Room, Error> {
+            if name.trim().is_empty() {
+                return Err(Error::bad_request("room name can not be empty"));
             }
+            let game = Game::new(settings)?;
+            self.room_id_cnt += 1;
+            Ok(Room::new(self.room_id_cnt, name, game))
         }
 
-        pub fn all_players(&self) -> Vec<i32> {
-            self.players
-                .iter()
-                .map(|(k, _)| k.clone())
-                .collect::<Vec<i32>>()
+        /// list the room, unless it is matched by the queue
+        pub fn add_room(&mut self, handle: RoomHandle, info: RoomInfo, matched: bool) {
+            self.rooms.insert(
+                info.id,
+                RoomEntry {
+                    handle,
+                    info,
+                    matched,
+                },
+            );
         }
 
-        pub fn type_of_player(&self, player_id: i32, room_id: i32) -> Option<i32> {
-            match self.rooms.get(&room_id) {
-                Some(room) => {
-                    if room.game_players.contains_key(&player_id) {
-                        Some(room.game_players.get(&player_id).unwrap().to_i32())
-                    } else if room.game_observers.contains(&player_id) {
-                        Some(PlayerType::OBSERVER.to_i32())
-                    } else {
-                        None
-                    }
+        /// keep the entry of the room up to date, a matched room is dropped once it is empty
+        pub fn update_room(&mut self, info: RoomInfo) {
+            match self.rooms.get_mut(&info.id) {
+                Some(entry)
+                    if entry.matched
+                        && info.game_players.is_empty()
+                        && info.game_observers.is_empty() =>
+                {
+                    self.rooms.remove(&info.id);
+                    self.games.remove(&info.id);
                 }
-                None => None
+                Some(entry) => entry.info = info,
+                None => {}
+            }
+        }
+
+        /// rooms with the ratings of their players who have an account
+        pub fn room_list(&self) -> Vec<ListedRoom> {
+            let mut rooms = self
+                .rooms
+                .values()
+                .filter(|v| !v.matched)
+                .map(|v| ListedRoom {
+                    ratings: v
+                        .info
+                        .game_players
+                        .keys()
+                        .filter_map(|id| self.players.get(id))
+                        .filter(|v| v.account.is_some())
+                        .map(|v| (v.id, v.rating.view()))
+                        .collect(),
+                    info: v.info.clone(),
+                })
+                .collect::<Vec<ListedRoom>>();
+            rooms.sort_by_key(|v| v.info.id);
+            rooms
+        }
+
+        /// send a lobby event to every player
+        pub fn broadcast(&mut self, event: &Event) {
+            self.lobby_event_seq += 1;
+            let msg = event.to_json(self.lobby_event_seq);
+            for player in self.players.values() {
+                // ignore failure, the connection is closing
+                player.sender.send(msg.clone()).unwrap_or_default();
             }
         }
     }
 
-    pub struct Game {
+    /// pieces on a board of row_size x col_size
+    #[derive(Clone, PartialEq)]
+    pub struct Board {
         row_size: usize,
         col_size: usize,
-        board: Vec<Vec<PieceType>>,
+        cells: Vec<Vec<PieceType>>,
     }
-    impl Game {
-        fn new(row_size: usize, col_size: usize) -> Game {
-            let mut game = Game {
+    impl Board {
+        pub fn new(row_size: usize, col_size: usize) -> Board {
+            Board {
                 row_size,
                 col_size,
-                board: Vec::with_capacity(row_size),
-            };
-            game.board.resize(row_size, Vec::with_capacity(col_size));
-            for i in 0..col_size {
-                game.board[i].resize(col_size, PieceType::EMPTY);
+                cells: vec![vec![PieceType::EMPTY; col_size]; row_size],
             }
-            game
         }
-        fn to_json(&self) -> String {
-            format!(
-                "{{\"row_size\":{},\"col_size\":{}}}",
-                self.row_size, self.col_size
-            )
+        pub fn row_size(&self) -> usize {
+            self.row_size
         }
-        fn reset(&mut self) {
-            for i in 0..self.row_size {
-                self.board[i].fill(PieceType::EMPTY);
+        pub fn col_size(&self) -> usize {
+            self.col_size
+        }
+        pub fn get(&self, row_i: usize, col_j: usize) -> PieceType {
+            self.cells[row_i][col_j]
+        }
+        pub fn set(&mut self, row_i: usize, col_j: usize, piece_type: PieceType) {
+            self.cells[row_i][col_j] = piece_type;
+        }
+        fn clear(&mut self) {
+            for row in self.cells.iter_mut() {
+                row.fill(PieceType::EMPTY);
+            }
+        }
+        /// every position, row by row
+        pub fn positions(&self) -> impl Iterator<Item = (usize, usize)> {
+            let col_size = self.col_size;
+            (0..self.row_size).flat_map(move |row_i| (0..col_size).map(move |col_j| (row_i, col_j)))
+        }
+        /// position (d_row, d_col) times steps away from (row_i, col_j), if on board
+        pub fn step(
+            &self,
+            (row_i, col_j): (usize, usize),
+            (d_row, d_col): (i32, i32),
+            steps: i32,
+        ) -> Option<(usize, usize)> {
+            let row_i = row_i as i32 + d_row * steps;
+            let col_j = col_j as i32 + d_col * steps;
+            if row_i < 0
+                || col_j < 0
+                || row_i >= self.row_size as i32
+                || col_j >= self.col_size as i32
+            {
+                return None;
             }
+            Some((row_i as usize, col_j as usize))
         }
-        fn put_piece(&mut self, row_i: usize, col_j: usize, piece_type: PieceType) {
-            self.board[row_i][col_j] = piece_type;
+        /// the unbroken line of pieces like the one at pos going through pos in direction dir,
+        /// from one end to the other
+        pub fn run(&self, pos: (usize, usize), dir: (i32, i32)) -> Vec<(usize, usize)> {
+            let piece_type = self.get(pos.0, pos.1);
+            let mut start = pos;
+            while let Some(prev) = self.step(start, dir, -1) {
+                if self.get(prev.0, prev.1) != piece_type {
+                    break;
+                }
+                start = prev;
+            }
+            let mut line = vec![start];
+            while let Some(next) = self.step(*line.last().unwrap(), dir, 1) {
+                if self.get(next.0, next.1) != piece_type {
+                    break;
+                }
+                line.push(next);
+            }
+            line
         }
     }
 
-    struct Room {
-        id: i32,
-        game_players: HashMap<i32, PlayerType>,
-        game_observers: HashSet<i32>,
-        name: String,
-        pub game: Game,
+    pub struct Game {
+        /// number of pieces in a row needed to win
+        win_length: usize,
+        rule: RuleSet,
+        opening: Opening,
+        board: Board,
+        /// piece type of the side to move
+        turn: PieceType,
+        /// all moves in order
+        moves: Vec<Move>,
+        /// piece type of the player waiting for the opponent to accept a takeback
+        undo_requester: Option<PieceType>,
+        /// None while the game goes on
+        result: Option<GameResult>,
+        /// piece type of the player waiting for the opponent to accept a draw
+        draw_offerer: Option<PieceType>,
+        /// piece type of the player waiting for the opponent to agree to reset a game in progress
+        reset_requester: Option<PieceType>,
+        phase: OpeningPhase,
+        /// PLAYER1 holds white and PLAYER2 black, decided by the opening
+        swapped: bool,
+        /// number of moves played in the opening, they can not be taken back
+        opening_len: usize,
+        /// None for untimed games
+        clocks: Option<Clocks>,
+        rated: bool,
     }
-    impl Room {
-        fn new(id: i32, name: String) -> Room {
-            Room {
-                id,
-                name,
-                game_players: HashMap::new(),
-                game_observers: HashSet::new(),
-                game: Game::new(10, 10),
+    impl Game {
+        /// an empty game, settings out of the limits are rejected
+        fn new(settings: &GameSettings) -> Result<Game, Error> {
+            let GameSettings {
+                row_size,
+                col_size,
+                win_length,
+                rule,
+                opening,
+                time_control,
+                rated,
+            } = *settings;
+            let board_sizes = MIN_BOARD_SIZE..=MAX_BOARD_SIZE;
+            if !board_sizes.contains(&row_size) || !board_sizes.contains(&col_size) {
+                return Err(Error::bad_request(&format!(
+                    "row_size and col_size must be between {} and {}",
+                    MIN_BOARD_SIZE, MAX_BOARD_SIZE
+                )));
+            }
+            let max_win_length = MAX_WIN_LENGTH.min(row_size.max(col_size));
+            if !(MIN_WIN_LENGTH..=max_win_length).contains(&win_length) {
+                return Err(Error::bad_request(&format!(
+                    "win_length must be between {} and {}",
+                    MIN_WIN_LENGTH, max_win_length
+                )));
+            }
+            match rule.win_length() {
+                Some(v) if v != win_length => {
+                    return Err(Error::bad_request(&format!(
+                        "the rule is played with win_length {}",
+                        v
+                    )));
+                }
+                _ => {}
+            }
+            if opening != Opening::Free && win_length < 5 {
+                return Err(Error::bad_request(
+                    "openings are played with win_length 5 or more",
+                ));
+            }
+            if let Some(time_control) = time_control {
+                time_control.validate()?;
             }
+            Ok(Game {
+                win_length,
+                rule,
+                opening,
+                board: Board::new(row_size, col_size),
+                turn: PieceType::BLACK,
+                moves: Vec::new(),
+                undo_requester: None,
+                result: None,
+                draw_offerer: None,
+                reset_requester: None,
+                phase: opening.first_phase(),
+                swapped: false,
+                opening_len: 0,
+                clocks: time_control.map(Clocks::new),
+                rated,
+            })
         }
-        fn to_json(&self) -> String {
-            format!(
-                "{{\"id\":{},\"name\":\"{}\",\"game_players\":{{{}}},\"game_observers\": [{}],\"game\":{}}}",
-                self.id,
-                self.name,
-                self.game_players
-                    .iter()
-                    .map(|(id,player_type)| {format!("\"{}\":{}", id, player_type.to_i32())})
-                    .collect::<Vec<String>>()
-                    .join(","),
-                self.game_observers
+        fn settings(&self) -> GameSettings {
+            GameSettings {
+                row_size: self.board.row_size,
+                col_size: self.board.col_size,
+                win_length: self.win_length,
+                rule: self.rule,
+                opening: self.opening,
+                time_control: self.clocks.as_ref().map(|v| v.time_control()),
+                rated: self.rated,
+            }
+        }
+        /// settings plus board, moves, side to move and status of the game
+        fn state(&self) -> GameState {
+            GameState {
+                settings: self.settings(),
+                board: self.board.cells.clone(),
+                moves: self
+                    .moves
                     .iter()
-                    .map(|v| {format!("{}", v)})
-                    .collect::<Vec<String>>()
-                    .join(","),
-                self.game.to_json()
-            )
+                    .map(|v| (v.row_i, v.col_j, v.piece_type))
+                    .collect(),
+                turn: self.turn,
+                undo_requester: self.undo_requester.unwrap_or(PieceType::EMPTY),
+                draw_offerer: self.draw_offerer.unwrap_or(PieceType::EMPTY),
+                reset_requester: self.reset_requester.unwrap_or(PieceType::EMPTY),
+                status: self.status(),
+                reason: self.result.as_ref().map(|v| v.reason),
+                line: self
+                    .result
+                    .as_ref()
+                    .map(|v| v.line.clone())
+                    .unwrap_or_default(),
+                forbidden: self.forbidden_points(),
+                opening: self.opening_view(),
+                clocks: self.clocks_view(),
+            }
         }
-        fn add_player(&mut self, player_id: i32) {
-            match self.game_players.len() {
-                0 => {
-                    self.game_players.insert(player_id, PlayerType::PLAYER1);
+        /// time left of both sides right now
+        fn clocks_view(&self) -> Option<ClocksView> {
+            self.clocks.as_ref().map(|v| v.view(Instant::now()))
+        }
+        /// when the clock of the side to move runs out
+        fn deadline(&self) -> Option<Instant> {
+            self.clocks.as_ref().and_then(|v| v.deadline())
+        }
+        /// end the game if the clock of the side to move has run out, return whether it did
+        fn check_timeout(&mut self) -> bool {
+            let flagged = match self.clocks.as_ref().and_then(|v| v.flagged(Instant::now())) {
+                Some(v) if !self.is_over() => v,
+                _ => return false,
+            };
+            let status = match flagged {
+                PieceType::BLACK => GameStatus::WhiteWon,
+                _ => GameStatus::BlackWon,
+            };
+            self.end(status, EndReason::Timeout, Vec::new());
+            true
+        }
+        fn opening_view(&self) -> OpeningView {
+            OpeningView {
+                phase: self.phase.clone(),
+                player_type: self
+                    .phase
+                    .actor()
+                    .map_or(PlayerType::OBSERVER, |v| self.seat_of(v))
+                    .to_i32(),
+                swapped: self.swapped,
+            }
+        }
+        fn reset(&mut self) {
+            self.board.clear();
+            self.turn = PieceType::BLACK;
+            self.moves.clear();
+            self.undo_requester = None;
+            self.result = None;
+            self.draw_offerer = None;
+            self.reset_requester = None;
+            self.phase = self.opening.first_phase();
+            self.swapped = false;
+            self.opening_len = 0;
+            if let Some(clocks) = self.clocks.as_mut() {
+                clocks.reset();
+            }
+        }
+        fn status(&self) -> GameStatus {
+            self.result
+                .as_ref()
+                .map_or(GameStatus::InProgress, |v| v.status)
+        }
+        fn is_over(&self) -> bool {
+            self.result.is_some()
+        }
+        fn end(&mut self, status: GameStatus, reason: EndReason, line: Vec<(usize, usize)>) {
+            self.undo_requester = None;
+            self.draw_offerer = None;
+            self.reset_requester = None;
+            if let Some(clocks) = self.clocks.as_mut() {
+                clocks.stop(Instant::now());
+            }
+            self.result = Some(GameResult {
+                status,
+                reason,
+                line,
+            });
+        }
+        /// the player of piece_type gives up
+        fn resign(&mut self, piece_type: PieceType) -> Result<(), MoveError> {
+            if self.is_over() {
+                return Err(MoveError::GameOver);
+            }
+            let status = match piece_type {
+                PieceType::BLACK => GameStatus::WhiteWon,
+                _ => GameStatus::BlackWon,
+            };
+            self.end(status, EndReason::Resign, Vec::new());
+            Ok(())
+        }
+        /// offer a draw, or agree to one if the opponent offered it already.
+        /// Return whether the game ended in a draw
+        fn offer_draw(&mut self, piece_type: PieceType) -> Result<bool, MoveError> {
+            if self.is_over() {
+                return Err(MoveError::GameOver);
+            }
+            match self.draw_offerer {
+                Some(v) if v == piece_type => Err(MoveError::DrawOfferPending),
+                Some(_) => {
+                    self.end(GameStatus::Draw, EndReason::DrawAgreed, Vec::new());
+                    Ok(true)
                 }
-                1 => {
-                    match self.game_players.iter().next().unwrap().1 {
-                        PlayerType::PLAYER1 => {
-                            self.game_players.insert(player_id, PlayerType::PLAYER2);
-                        }
-                        PlayerType::PLAYER2 => {
-                            self.game_players.insert(player_id, PlayerType::PLAYER1);
-                        }
-                        PlayerType::OBSERVER => {}
-                    };
+                None => {
+                    self.draw_offerer = Some(piece_type);
+                    Ok(false)
                 }
-                _ => {
-                    self.game_observers.insert(player_id);
+            }
+        }
+        fn accept_draw(&mut self, piece_type: PieceType) -> Result<(), MoveError> {
+            if self.is_over() {
+                return Err(MoveError::GameOver);
+            }
+            match self.draw_offerer {
+                Some(v) if v != piece_type => {
+                    self.end(GameStatus::Draw, EndReason::DrawAgreed, Vec::new());
+                    Ok(())
                 }
-            };
+                _ => Err(MoveError::NoDrawOffer),
+            }
         }
-        fn remove_player(&mut self, player_id: i32) {
-            self.game_players.remove(&player_id);
-            self.game_observers.remove(&player_id);
+        /// start over once the game is over or before it started, otherwise ask the opponent,
+        /// a request of both resets it. Return whether the game was reset
+        fn request_reset(&mut self, piece_type: PieceType) -> Result<bool, MoveError> {
+            if !self.is_over() && !self.moves.is_empty() {
+                match self.reset_requester {
+                    Some(v) if v == piece_type => return Err(MoveError::ResetPending),
+                    Some(_) => {}
+                    None => {
+                        self.reset_requester = Some(piece_type);
+                        return Ok(false);
+                    }
+                }
+            }
+            self.reset();
+            Ok(true)
         }
-        pub fn all_players(&self) -> Vec<i32> {
-            self.game_players
-                .iter()
-                .map(|(k, _)| k)
-                .chain(self.game_observers.iter())
-                .map(|v| v.clone())
-                .collect::<Vec<i32>>()
+        /// the player of piece_type left for reason, it loses a game that has started
+        fn forfeit(&mut self, piece_type: PieceType, reason: EndReason) {
+            if !self.is_over() && !self.moves.is_empty() {
+                let status = match piece_type {
+                    PieceType::BLACK => GameStatus::WhiteWon,
+                    _ => GameStatus::BlackWon,
+                };
+                self.end(status, reason, Vec::new());
+            }
+        }
+        /// end a game that has started but not finished
+        fn abort(&mut self) {
+            if !self.is_over() && !self.moves.is_empty() {
+                self.end(GameStatus::Aborted, EndReason::PlayerLeft, Vec::new());
+            }
+        }
+        /// piece type the seat plays with
+        fn color_of(&self, seat: PlayerType) -> PieceType {
+            match (seat, self.swapped) {
+                (PlayerType::OBSERVER, _) => PieceType::EMPTY,
+                (PlayerType::PLAYER1, false) | (PlayerType::PLAYER2, true) => PieceType::BLACK,
+                _ => PieceType::WHITE,
+            }
+        }
+        fn seat_of(&self, actor: Actor) -> PlayerType {
+            match actor {
+                Actor::Player1 => PlayerType::PLAYER1,
+                Actor::Player2 => PlayerType::PLAYER2,
+                Actor::Color(color) if self.color_of(PlayerType::PLAYER1) == color => {
+                    PlayerType::PLAYER1
+                }
+                Actor::Color(_) => PlayerType::PLAYER2,
+            }
+        }
+        /// the seat has to act in the current phase of the opening
+        fn check_actor(&self, seat: PlayerType) -> Result<(), MoveError> {
+            if self.is_over() {
+                return Err(MoveError::GameOver);
+            }
+            match self.phase.actor() {
+                None => Err(MoveError::WrongPhase),
+                Some(actor) if self.seat_of(actor) != seat => Err(MoveError::NotYourTurn),
+                Some(_) => Ok(()),
+            }
+        }
+        /// check piece_type can go on point of board, board is left as it was
+        fn check_point(
+            &self,
+            board: &mut Board,
+            (row_i, col_j): (usize, usize),
+            piece_type: PieceType,
+        ) -> Result<(), MoveError> {
+            if row_i >= board.row_size || col_j >= board.col_size {
+                return Err(MoveError::OutOfBounds);
+            }
+            if board.get(row_i, col_j) != PieceType::EMPTY {
+                return Err(MoveError::Occupied);
+            }
+            match self.rule.forbidden(board, (row_i, col_j), piece_type) {
+                Some(err) => Err(err),
+                None => Ok(()),
+            }
+        }
+        /// put stones of alternating colors continuing the moves, all or none of them
+        fn put_stones(&mut self, stones: &[(usize, usize)]) -> Result<Vec<Move>, MoveError> {
+            let mut board = self.board.clone();
+            let mut placed = Vec::new();
+            for &(row_i, col_j) in stones {
+                let piece_type = PieceType::of_move(self.moves.len() + placed.len());
+                self.check_point(&mut board, (row_i, col_j), piece_type)?;
+                board.set(row_i, col_j, piece_type);
+                placed.push(Move {
+                    row_i,
+                    col_j,
+                    piece_type,
+                });
+            }
+            self.board = board;
+            self.moves.extend(placed.iter().copied());
+            self.turn = PieceType::of_move(self.moves.len());
+            Ok(placed)
+        }
+        fn set_phase(&mut self, phase: OpeningPhase) {
+            if phase == OpeningPhase::Done {
+                self.opening_len = self.moves.len();
+            }
+            self.phase = phase;
+            let to_act = self.to_act();
+            if let Some(clocks) = self.clocks.as_mut() {
+                clocks.switch(to_act, Instant::now());
+            }
+        }
+        /// piece type of the side that has to act, in the opening or the game
+        fn to_act(&self) -> PieceType {
+            match self.phase.actor() {
+                Some(actor) => self.color_of(self.seat_of(actor)),
+                None => self.turn,
+            }
+        }
+        /// run the clock of the side to act once both seats are taken
+        fn start_clock(&mut self) {
+            if self.is_over() {
+                return;
+            }
+            let to_act = self.to_act();
+            if let Some(clocks) = self.clocks.as_mut() {
+                clocks.start(to_act, Instant::now());
+            }
+        }
+        /// put the stones of the current phase, with the number of 5th moves to propose
+        /// when placing the 4th stone of soosorv8
+        fn place_opening(
+            &mut self,
+            seat: PlayerType,
+            stones: &[(usize, usize)],
+            fifth_count: Option<usize>,
+        ) -> Result<Vec<Move>, MoveError> {
+            self.check_actor(seat)?;
+            let phase = self
+                .phase
+                .after_placing(self.opening, stones.len(), fifth_count)?;
+            let placed = self.put_stones(stones)?;
+            self.set_phase(phase);
+            Ok(placed)
+        }
+        /// the seat takes color, swapping with the opponent if it does not hold it yet
+        fn choose_color(&mut self, seat: PlayerType, color: PieceType) -> Result<(), MoveError> {
+            self.check_actor(seat)?;
+            let phase = self.phase.after_choosing(self.opening)?;
+            if color != self.color_of(seat) {
+                self.swapped = !self.swapped;
+            }
+            self.set_phase(phase);
+            Ok(())
+        }
+        fn propose_fifth(
+            &mut self,
+            seat: PlayerType,
+            points: &[(usize, usize)],
+        ) -> Result<(), MoveError> {
+            self.check_actor(seat)?;
+            let fifth_count = match self.phase {
+                OpeningPhase::ProposeFifth { fifth_count } => fifth_count,
+                _ => return Err(MoveError::WrongPhase),
+            };
+            if points.len() != fifth_count {
+                return Err(MoveError::WrongStoneCount);
+            }
+            let mut board = self.board.clone();
+            for (i, &point) in points.iter().enumerate() {
+                if points[..i].contains(&point) {
+                    return Err(MoveError::DuplicatePoint);
+                }
+                self.check_point(&mut board, point, PieceType::BLACK)?;
+            }
+            self.set_phase(OpeningPhase::ChooseFifth {
+                proposals: points.to_vec(),
+            });
+            Ok(())
+        }
+        /// play the proposal at point as the 5th move, which ends the opening
+        fn choose_fifth(
+            &mut self,
+            seat: PlayerType,
+            point: (usize, usize),
+        ) -> Result<Move, MoveError> {
+            self.check_actor(seat)?;
+            match &self.phase {
+                OpeningPhase::ChooseFifth { proposals } if proposals.contains(&point) => {}
+                OpeningPhase::ChooseFifth { .. } => return Err(MoveError::NotAProposal),
+                _ => return Err(MoveError::WrongPhase),
+            }
+            let placed = self.put_stones(&[point])?;
+            self.set_phase(OpeningPhase::Done);
+            Ok(placed[0])
+        }
+        fn put_piece(
+            &mut self,
+            row_i: usize,
+            col_j: usize,
+            piece_type: PieceType,
+        ) -> Result<(), MoveError> {
+            if self.is_over() {
+                return Err(MoveError::GameOver);
+            }
+            if self.phase != OpeningPhase::Done {
+                return Err(MoveError::OpeningInProgress);
+            }
+            // the timer of the room may not have fired yet
+            if self.check_timeout() {
+                return Err(MoveError::GameOver);
+            }
+            if row_i >= self.board.row_size || col_j >= self.board.col_size {
+                return Err(MoveError::OutOfBounds);
+            }
+            if self.board.get(row_i, col_j) != PieceType::EMPTY {
+                return Err(MoveError::Occupied);
+            }
+            if piece_type != self.turn {
+                return Err(MoveError::NotYourTurn);
+            }
+            if let Some(err) = self
+                .rule
+                .forbidden(&mut self.board, (row_i, col_j), piece_type)
+            {
+                return Err(err);
+            }
+            self.board.set(row_i, col_j, piece_type);
+            self.moves.push(Move {
+                row_i,
+                col_j,
+                piece_type,
+            });
+            // a move implicitly withdraws any pending takeback, draw offer or reset request
+            self.undo_requester = None;
+            self.draw_offerer = None;
+            self.reset_requester = None;
+            self.turn = match piece_type {
+                PieceType::BLACK => PieceType::WHITE,
+                _ => PieceType::BLACK,
+            };
+            if let Some(line) = self
+                .rule
+                .winning_line(&self.board, (row_i, col_j), self.win_length)
+            {
+                let status = match piece_type {
+                    PieceType::BLACK => GameStatus::BlackWon,
+                    _ => GameStatus::WhiteWon,
+                };
+                self.end(status, EndReason::Five, line);
+            } else if self.moves.len() == self.board.row_size * self.board.col_size {
+                self.end(GameStatus::Draw, EndReason::BoardFull, Vec::new());
+            } else if let Some(clocks) = self.clocks.as_mut() {
+                clocks.press(self.turn, Instant::now());
+            }
+            Ok(())
+        }
+        fn request_undo(&mut self, piece_type: PieceType) -> Result<(), MoveError> {
+            if self.is_over() {
+                return Err(MoveError::GameOver);
+            }
+            if self.phase != OpeningPhase::Done {
+                return Err(MoveError::OpeningInProgress);
+            }
+            if self.undo_requester.is_some() {
+                return Err(MoveError::UndoPending);
+            }
+            if !self.moves[self.opening_len..]
+                .iter()
+                .any(|v| v.piece_type == piece_type)
+            {
+                return Err(MoveError::NothingToUndo);
+            }
+            self.undo_requester = Some(piece_type);
+            Ok(())
+        }
+        /// on acceptance take back moves till the requester's last one, so it is the requester's
+        /// turn again
+        fn answer_undo(
+            &mut self,
+            piece_type: PieceType,
+            accept: bool,
+        ) -> Result<Vec<Move>, MoveError> {
+            let requester = match self.undo_requester {
+                Some(v) if v != piece_type => v,
+                _ => return Err(MoveError::NoUndoRequest),
+            };
+            self.undo_requester = None;
+            let mut undone = Vec::new();
+            if accept {
+                while let Some(last) = self.moves.pop() {
+                    self.board.set(last.row_i, last.col_j, PieceType::EMPTY);
+                    undone.push(last);
+                    if last.piece_type == requester {
+                        break;
+                    }
+                }
+                self.turn = requester;
+                if let Some(clocks) = self.clocks.as_mut() {
+                    clocks.switch(requester, Instant::now());
+                }
+            }
+            Ok(undone)
+        }
+        /// points the side to move can not play on, empty once the game is over
+        fn forbidden_points(&self) -> Vec<(usize, usize)> {
+            if self.is_over() {
+                return Vec::new();
+            }
+            self.rule.forbidden_points(&self.board, self.turn)
+        }
+    }
+
+    /// what the bot of a room has to do
+    pub enum BotTurn {
+        /// put a piece, it is the bot's turn in position
+        Move(Position),
+        /// the opponent asked to take back a move
+        AnswerUndo,
+        /// the opponent asked to reset the game
+        AnswerReset,
+    }
+
+    /// a room with its members and game, owned by the task of the room
+    pub struct Room {
+        id: i32,
+        game_players: HashMap<i32, PlayerType>,
+        game_observers: HashSet<i32>,
+        /// seated players who lost connection, with when their seat is given up
+        disconnected: HashMap<i32, Instant>,
+        /// nickname of each member
+        nicknames: HashMap<i32, String>,
+        name: String,
+        /// seq of the latest event
+        event_seq: u64,
+        game: Game,
+    }
+    impl Room {
+        fn new(id: i32, name: String, game: Game) -> Room {
+            Room {
+                id,
+                name,
+                game_players: HashMap::new(),
+                game_observers: HashSet::new(),
+                disconnected: HashMap::new(),
+                nicknames: HashMap::new(),
+                event_seq: 0,
+                game,
+            }
+        }
+        pub fn id(&self) -> i32 {
+            self.id
+        }
+        pub fn info(&self) -> RoomInfo {
+            self.view(self.game.settings())
+        }
+        /// like info, but with the full state of the game instead of its settings only
+        pub fn state(&self) -> RoomState {
+            let mut game = self.game.state();
+            game.status = self.status();
+            self.view(game)
+        }
+        /// status of the game, waiting as long as a seat is free
+        pub fn status(&self) -> GameStatus {
+            match self.game.status() {
+                GameStatus::InProgress if self.game_players.len() < 2 => GameStatus::Waiting,
+                status => status,
+            }
+        }
+        fn view<G>(&self, game: G) -> RoomView<G> {
+            let mut game_observers = self.game_observers.iter().copied().collect::<Vec<i32>>();
+            game_observers.sort_unstable();
+            let mut disconnected = self.disconnected.keys().copied().collect::<Vec<i32>>();
+            disconnected.sort_unstable();
+            RoomView {
+                id: self.id,
+                name: self.name.clone(),
+                game_players: self
+                    .game_players
+                    .iter()
+                    .map(|(id, player_type)| (*id, player_type.to_i32()))
+                    .collect(),
+                game_observers,
+                disconnected,
+                nicknames: self
+                    .nicknames
+                    .iter()
+                    .map(|(id, nickname)| (*id, nickname.clone()))
+                    .collect(),
+                seq: self.event_seq,
+                game,
+            }
+        }
+        /// enter as a player if a seat is free, or as an observer. Entering again only
+        /// updates the nickname
+        pub fn enter(&mut self, player_id: i32, nickname: String) -> RoomState {
+            if !self.all_players().contains(&player_id) {
+                self.add_player(player_id);
+            }
+            self.nicknames.insert(player_id, nickname);
+            self.state()
+        }
+        pub fn nickname(&self, player_id: i32) -> String {
+            self.nicknames.get(&player_id).cloned().unwrap_or_default()
+        }
+        /// the member changed its nickname, return the event telling the room
+        pub fn rename(&mut self, player_id: i32, nickname: String) -> Result<Event, Error> {
+            self.type_of_player(player_id)?;
+            self.nicknames.insert(player_id, nickname.clone());
+            Ok(Event::SetNickname {
+                room_id: self.id,
+                player_id,
+                nickname,
+            })
+        }
+        /// seat the bot called nickname in the free seat at the request of player_id,
+        /// return the `enter_room` event
+        pub fn add_bot(&mut self, player_id: i32, nickname: String) -> Result<Event, Error> {
+            self.type_of_player(player_id)?;
+            if self.game_players.contains_key(&BOT_ID) {
+                return Err(Error::bad_request("there is a bot in the room already"));
+            }
+            if self.game_players.len() >= 2 {
+                return Err(Error::bad_request("both seats are taken"));
+            }
+            if self.game.opening != Opening::Free {
+                return Err(Error::bad_request(
+                    "bots only play games without an opening",
+                ));
+            }
+            self.add_player(BOT_ID);
+            self.nicknames.insert(BOT_ID, nickname.clone());
+            Ok(Event::EnterRoom {
+                room_id: self.id,
+                player_id: BOT_ID,
+                player_type: self.type_of_player(BOT_ID)?,
+                nickname,
+            })
+        }
+        /// what the bot has to do now, if anything
+        pub fn bot_turn(&self) -> Option<BotTurn> {
+            let color = self.game.color_of(*self.game_players.get(&BOT_ID)?);
+            if self.game_players.len() < 2
+                || self.game.is_over()
+                || self.game.phase != OpeningPhase::Done
+            {
+                return None;
+            }
+            if self.game.reset_requester.is_some_and(|v| v != color) {
+                return Some(BotTurn::AnswerReset);
+            }
+            match self.game.undo_requester {
+                Some(requester) if requester != color => Some(BotTurn::AnswerUndo),
+                Some(_) => None,
+                None if self.game.turn == color => Some(BotTurn::Move(Position {
+                    board: self.game.board.clone(),
+                    to_move: color,
+                    win_length: self.game.win_length,
+                    rule: self.game.rule,
+                })),
+                None => None,
+            }
+        }
+        fn add_player(&mut self, player_id: i32) {
+            match self.game_players.len() {
+                0 => {
+                    self.game_players.insert(player_id, PlayerType::PLAYER1);
+                }
+                1 => {
+                    match self.game_players.iter().next().unwrap().1 {
+                        PlayerType::PLAYER1 => {
+                            self.game_players.insert(player_id, PlayerType::PLAYER2);
+                        }
+                        PlayerType::PLAYER2 => {
+                            self.game_players.insert(player_id, PlayerType::PLAYER1);
+                        }
+                        PlayerType::OBSERVER => {}
+                    };
+                    self.game.start_clock();
+                }
+                _ => {
+                    self.game_observers.insert(player_id);
+                }
+            };
+        }
+        /// leave room, return the `exit_room` event. A player leaving a started game loses it,
+        /// which is aborted instead if the opponent is gone already, a game that has not
+        /// started waits for the seat to be taken again
+        pub fn exit(&mut self, player_id: i32) -> Result<Event, Error> {
+            let player_type = self.type_of_player(player_id)?;
+            if let Some(&seat) = self.game_players.get(&player_id) {
+                if self.game_players.len() == 2 {
+                    self.game
+                        .forfeit(self.game.color_of(seat), EndReason::PlayerLeft);
+                } else {
+                    self.game.abort();
+                }
+                if !self.has_started() {
+                    // stop the clock till the seat is taken again
+                    self.game.reset();
+                }
+            }
+            self.game_players.remove(&player_id);
+            self.game_observers.remove(&player_id);
+            self.disconnected.remove(&player_id);
+            Ok(Event::ExitRoom {
+                room_id: self.id,
+                player_id,
+                player_type,
+                nickname: self.nicknames.remove(&player_id).unwrap_or_default(),
+            })
+        }
+        /// the connection of a seated player dropped, hold the seat till deadline.
+        /// Return whether it is held, observers and outsiders have nothing to hold
+        pub fn disconnect(&mut self, player_id: i32, deadline: Instant) -> bool {
+            if !self.game_players.contains_key(&player_id) {
+                return false;
+            }
+            self.disconnected.insert(player_id, deadline);
+            true
+        }
+        /// the player is back in time, return whether it had lost connection
+        pub fn reconnect(&mut self, player_id: i32) -> bool {
+            self.disconnected.remove(&player_id).is_some()
+        }
+        /// players whose seat is no longer held by now leave the room and lose the game,
+        /// which is aborted instead if the opponent is gone too. Return their `exit_room` events
+        pub fn expire_disconnected(&mut self, now: Instant) -> Vec<Event> {
+            let mut expired = self
+                .disconnected
+                .iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(player_id, _)| *player_id)
+                .collect::<Vec<i32>>();
+            expired.sort_unstable();
+            let mut exited = Vec::new();
+            for player_id in expired {
+                self.disconnected.remove(&player_id);
+                if let Some(&seat) = self.game_players.get(&player_id) {
+                    if self.disconnected.is_empty() {
+                        self.game
+                            .forfeit(self.game.color_of(seat), EndReason::Abandoned);
+                    } else {
+                        self.game.abort();
+                    }
+                }
+                // ignore failure, the player is in the room
+                exited.extend(self.exit(player_id).ok());
+            }
+            exited
+        }
+        /// seat of the player, observers and outsiders have none
+        fn seat_of(&self, player_id: i32) -> Result<PlayerType, MoveError> {
+            match self.game_players.get(&player_id) {
+                Some(player_type) => Ok(*player_type),
+                None if self.game_observers.contains(&player_id) => Err(MoveError::NotAPlayer),
+                None => Err(MoveError::NotInRoom),
+            }
+        }
+        /// seat of the player, who can only play once both seats are taken
+        fn playing_seat(&self, player_id: i32) -> Result<PlayerType, MoveError> {
+            let seat = self.seat_of(player_id)?;
+            if self.game_players.len() < 2 {
+                return Err(MoveError::WaitingForOpponent);
+            }
+            Ok(seat)
+        }
+        /// piece type the player plays with
+        fn piece_type_of(&self, player_id: i32) -> Result<PieceType, MoveError> {
+            Ok(self.game.color_of(self.playing_seat(player_id)?))
+        }
+        pub fn type_of_player(&self, player_id: i32) -> Result<i32, Error> {
+            if let Some(player_type) = self.game_players.get(&player_id) {
+                Ok(player_type.to_i32())
+            } else if self.game_observers.contains(&player_id) {
+                Ok(PlayerType::OBSERVER.to_i32())
+            } else {
+                Err(MoveError::NotInRoom.into())
+            }
+        }
+        pub fn all_players(&self) -> Vec<i32> {
+            self.game_players
+                .keys()
+                .chain(self.game_observers.iter())
+                .copied()
+                .collect::<Vec<i32>>()
+        }
+
+        /// reset the game, only players of the room can do it. A game in progress needs both
+        /// of them, return whether it was reset or waits for the opponent
+        pub fn reset_game(&mut self, player_id: i32) -> Result<bool, Error> {
+            let seat = self.seat_of(player_id)?;
+            let reset = self.game.request_reset(self.game.color_of(seat))?;
+            if reset && self.game_players.len() == 2 {
+                self.game.start_clock();
+            }
+            Ok(reset)
+        }
+
+        /// put a piece of player, the piece type is decided by the player's seat
+        pub fn put_piece(
+            &mut self,
+            player_id: i32,
+            row_i: usize,
+            col_j: usize,
+        ) -> Result<PieceType, Error> {
+            let piece_type = self.piece_type_of(player_id)?;
+            self.game.put_piece(row_i, col_j, piece_type)?;
+            Ok(piece_type)
+        }
+
+        /// player asks the opponent to take back the last move
+        pub fn request_undo(&mut self, player_id: i32) -> Result<(), Error> {
+            let piece_type = self.piece_type_of(player_id)?;
+            Ok(self.game.request_undo(piece_type)?)
+        }
+
+        /// player answers the undo request of the opponent, return the moves taken back
+        pub fn answer_undo(&mut self, player_id: i32, accept: bool) -> Result<Vec<Move>, Error> {
+            let piece_type = self.piece_type_of(player_id)?;
+            Ok(self.game.answer_undo(piece_type, accept)?)
+        }
+
+        /// snapshot of the room with the full game state, only for players in the room
+        pub fn room_state(&self, player_id: i32) -> Result<RoomState, Error> {
+            self.type_of_player(player_id)?;
+            Ok(self.state())
+        }
+
+        /// put opening stones of the player, return them
+        pub fn place_opening(
+            &mut self,
+            player_id: i32,
+            stones: &[(usize, usize)],
+            fifth_count: Option<usize>,
+        ) -> Result<Vec<Move>, Error> {
+            let seat = self.playing_seat(player_id)?;
+            Ok(self.game.place_opening(seat, stones, fifth_count)?)
+        }
+
+        pub fn choose_color(&mut self, player_id: i32, piece_type: PieceType) -> Result<(), Error> {
+            if piece_type == PieceType::EMPTY {
+                return Err(Error::bad_request("choose black or white"));
+            }
+            let seat = self.playing_seat(player_id)?;
+            Ok(self.game.choose_color(seat, piece_type)?)
+        }
+
+        pub fn propose_fifth(
+            &mut self,
+            player_id: i32,
+            points: &[(usize, usize)],
+        ) -> Result<(), Error> {
+            let seat = self.playing_seat(player_id)?;
+            Ok(self.game.propose_fifth(seat, points)?)
+        }
+
+        /// play one of the proposed 5th moves, return it
+        pub fn choose_fifth(
+            &mut self,
+            player_id: i32,
+            point: (usize, usize),
+        ) -> Result<Move, Error> {
+            let seat = self.playing_seat(player_id)?;
+            Ok(self.game.choose_fifth(seat, point)?)
+        }
+
+        pub fn opening(&self) -> OpeningView {
+            self.game.opening_view()
+        }
+
+        pub fn resign(&mut self, player_id: i32) -> Result<(), Error> {
+            let piece_type = self.piece_type_of(player_id)?;
+            Ok(self.game.resign(piece_type)?)
+        }
+
+        /// offer a draw, return whether the opponent had offered one too so it is a draw now
+        pub fn offer_draw(&mut self, player_id: i32) -> Result<bool, Error> {
+            let piece_type = self.piece_type_of(player_id)?;
+            Ok(self.game.offer_draw(piece_type)?)
+        }
+
+        pub fn accept_draw(&mut self, player_id: i32) -> Result<(), Error> {
+            let piece_type = self.piece_type_of(player_id)?;
+            Ok(self.game.accept_draw(piece_type)?)
+        }
+
+        /// points the side to move can not play on, only for players in the room
+        pub fn forbidden_points(&self, player_id: i32) -> Result<Vec<(usize, usize)>, Error> {
+            self.type_of_player(player_id)?;
+            Ok(self.game.forbidden_points())
+        }
+
+        pub fn is_over(&self) -> bool {
+            self.game.is_over()
+        }
+
+        /// time left of both sides, None for untimed games
+        pub fn clocks(&self) -> Option<ClocksView> {
+            self.game.clocks_view()
+        }
+
+        /// when the clock of the side to move runs out or a held seat is given up, whichever
+        /// comes first, the room checks them then
+        pub fn deadline(&self) -> Option<Instant> {
+            self.disconnected
+                .values()
+                .copied()
+                .chain(self.game.deadline())
+                .min()
+        }
+
+        /// end the game if the clock of the side to move has run out
+        pub fn check_timeout(&mut self) {
+            self.game.check_timeout();
+        }
+
+        /// whether the game has a move or is over already
+        pub fn has_started(&self) -> bool {
+            !self.game.moves.is_empty() || self.game.is_over()
+        }
+
+        /// ids of the seated players
+        pub fn seated(&self) -> Vec<i32> {
+            self.game_players.keys().copied().collect()
+        }
+
+        /// ids of the players holding black and white, once both seats are taken
+        pub fn players_by_color(&self) -> Option<(i32, i32)> {
+            let player_of = |color: PieceType| {
+                self.game_players
+                    .iter()
+                    .find(|(_, seat)| self.game.color_of(**seat) == color)
+                    .map(|(id, _)| *id)
+            };
+            Some((player_of(PieceType::BLACK)?, player_of(PieceType::WHITE)?))
+        }
+
+        /// the finished game for the record, None if it is not over or was aborted
+        pub fn record(&self, (black, white): (i32, i32)) -> Option<GameRecord> {
+            let result = self.game.result.as_ref()?;
+            if result.status == GameStatus::Aborted {
+                return None;
+            }
+            Some(GameRecord {
+                room_id: self.id,
+                black,
+                white,
+                status: result.status,
+                reason: result.reason,
+                moves: self.game.moves.len(),
+                rated: self.game.rated,
+            })
+        }
+
+        /// the `game_over` event if the game is over
+        pub fn game_over(&self) -> Option<GameOver> {
+            self.game.result.as_ref().map(|v| GameOver {
+                room_id: self.id,
+                status: v.status,
+                reason: v.reason,
+                winner: match v.status {
+                    GameStatus::BlackWon => PieceType::BLACK,
+                    GameStatus::WhiteWon => PieceType::WHITE,
+                    _ => PieceType::EMPTY,
+                },
+                line: v.line.clone(),
+            })
+        }
+
+        /// number the next event of the room
+        pub fn next_event_seq(&mut self) -> u64 {
+            self.event_seq += 1;
+            self.event_seq
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::clock::TimeControl;
+        use crate::rating::DEFAULT_RATING;
+
+        fn context() -> Context {
+            Context::new(Store::open(":memory:").unwrap())
+        }
+
+        /// a connected guest and its sender
+        fn guest(context: &mut Context) -> (i32, ClientSender) {
+            let (sender, _) = tokio::sync::mpsc::unbounded_channel();
+            let (closer, _) = tokio::sync::mpsc::unbounded_channel();
+            let (player_id, _) = context.add_player("127.0.0.1", sender.clone(), closer);
+            (player_id, sender)
+        }
+
+        /// a connected player with a new account called username, and its sender
+        fn account(context: &mut Context, username: &str) -> (i32, ClientSender) {
+            let (player_id, sender) = guest(context);
+            context.register(player_id, username, "hash").unwrap();
+            (player_id, sender)
+        }
+
+        fn record(black: i32, white: i32, status: GameStatus) -> GameRecord {
+            GameRecord {
+                room_id: 1,
+                black,
+                white,
+                status,
+                reason: EndReason::Abandoned,
+                moves: 9,
+                rated: true,
+            }
+        }
+
+        #[test]
+        fn forfeit_without_grace_is_rated() {
+            let mut context = context();
+            let (black, _) = account(&mut context, "Alice");
+            let (white, white_sender) = account(&mut context, "Bob");
+            context.game_started(1, &[black, white]);
+            context.disconnect(white, &white_sender, Duration::ZERO);
+            context.game_finished(&record(black, white, GameStatus::BlackWon));
+            let bob = context.store.find_account("Bob").unwrap().unwrap();
+            assert_eq!((bob.stats.games, bob.stats.losses), (1, 1));
+            assert!(bob.rating.rating < DEFAULT_RATING);
+            let alice = context.store.find_account("Alice").unwrap().unwrap();
+            assert_eq!((alice.stats.games, alice.stats.wins), (1, 1));
+            assert!(alice.rating.rating > DEFAULT_RATING);
+            assert_eq!(context.players[&black].rating, alice.rating);
+        }
+
+        #[test]
+        fn login_during_the_game_leaves_it_unrated() {
+            let mut context = context();
+            let (black, _) = account(&mut context, "Alice");
+            let (white, _) = guest(&mut context);
+            context.game_started(1, &[black, white]);
+            context.register(white, "Bob", "hash").unwrap();
+            context.game_finished(&record(black, white, GameStatus::WhiteWon));
+            let bob = context.store.find_account("Bob").unwrap().unwrap();
+            assert_eq!(bob.stats.games, 0);
+            assert_eq!(bob.rating.rating, DEFAULT_RATING);
+            let alice = context.store.find_account("Alice").unwrap().unwrap();
+            assert_eq!((alice.stats.games, alice.stats.losses), (1, 1));
+            assert_eq!(alice.rating.rating, DEFAULT_RATING);
+        }
+
+        /// guests with the ratings waiting in the queue for the default settings since start
+        fn queued(context: &mut Context, ratings: &[f64], start: Instant) -> Vec<i32> {
+            let mut player_ids = Vec::new();
+            for &rating in ratings {
+                let (player_id, _) = guest(context);
+                context.players.get_mut(&player_id).unwrap().rating.rating = rating;
+                let (rooms, _) = tokio::sync::mpsc::unbounded_channel();
+                context
+                    .join_queue(player_id, GameSettings::default(), rooms)
+                    .unwrap();
+                player_ids.push(player_id);
+            }
+            for entry in context.queue.iter_mut() {
+                entry.since = start;
+            }
+            player_ids
+        }
+
+        fn paired(matches: &[Match]) -> Vec<Vec<i32>> {
+            matches
+                .iter()
+                .map(|v| {
+                    let mut player_ids = v.players.iter().map(|v| v.0).collect::<Vec<i32>>();
+                    player_ids.sort_unstable();
+                    player_ids
+                })
+                .collect()
+        }
+
+        #[test]
+        fn queue_window_widens_while_waiting() {
+            let start = Instant::now();
+            let mut context = context();
+            queued(&mut context, &[DEFAULT_RATING], start);
+            let entry = &context.queue[0];
+            let secs = Duration::from_secs;
+            assert_eq!(entry.window(start), MATCH_WINDOW);
+            assert_eq!(entry.window(start + secs(4)), MATCH_WINDOW);
+            assert_eq!(
+                entry.window(start + secs(MATCH_WINDOW_STEP_SECS)),
+                MATCH_WINDOW + MATCH_WINDOW_STEP
+            );
+            assert_eq!(
+                entry.window(start + secs(3 * MATCH_WINDOW_STEP_SECS + 1)),
+                MATCH_WINDOW + 3.0 * MATCH_WINDOW_STEP
+            );
+            assert_eq!(entry.window(start + secs(3600)), MAX_MATCH_WINDOW);
+        }
+
+        #[test]
+        fn queue_pairs_the_closest_rating() {
+            let start = Instant::now();
+            let mut context = context();
+            let ids = queued(&mut context, &[1500.0, 1580.0, 1520.0], start);
+            let matches = context.take_matches(start);
+            assert_eq!(paired(&matches), [vec![ids[0], ids[2]]]);
+            assert_eq!(matches[0].room.seated().len(), 2);
+            assert_eq!(context.queue.len(), 1);
+            assert_eq!(context.queue[0].player_id, ids[1]);
+        }
+
+        #[test]
+        fn queue_pairs_once_the_windows_are_wide_enough() {
+            let start = Instant::now();
+            let mut context = context();
+            let ids = queued(&mut context, &[1500.0, 1700.0], start);
+            assert!(context.take_matches(start).is_empty());
+            let later = start + Duration::from_secs(MATCH_WINDOW_STEP_SECS);
+            assert!(context.take_matches(later).is_empty());
+            let later = start + Duration::from_secs(2 * MATCH_WINDOW_STEP_SECS);
+            assert_eq!(paired(&context.take_matches(later)), [vec![ids[0], ids[1]]]);
+            assert!(context.queue.is_empty());
+        }
+
+        #[test]
+        fn queue_pairs_only_the_same_settings() {
+            let start = Instant::now();
+            let mut context = context();
+            let ids = queued(&mut context, &[DEFAULT_RATING, DEFAULT_RATING], start);
+            let (rooms, _) = tokio::sync::mpsc::unbounded_channel();
+            let settings = GameSettings {
+                rule: RuleSet::Standard,
+                ..GameSettings::default()
+            };
+            context.join_queue(ids[1], settings, rooms).unwrap();
+            assert!(context.take_matches(start).is_empty());
+            assert_eq!(context.queue.len(), 2);
+        }
+
+        fn game_with(opening: Opening) -> Game {
+            Game::new(&GameSettings {
+                opening,
+                ..GameSettings::default()
+            })
+            .unwrap()
+        }
+
+        /// put stones on points in turn, starting with the side to move
+        fn play(game: &mut Game, points: &[(usize, usize)]) {
+            for &(row_i, col_j) in points {
+                game.put_piece(row_i, col_j, game.turn).unwrap();
+            }
+        }
+
+        #[test]
+        fn five_in_a_row_wins() {
+            let mut game = game_with(Opening::Free);
+            play(
+                &mut game,
+                &[
+                    (7, 3),
+                    (0, 0),
+                    (7, 4),
+                    (0, 1),
+                    (7, 5),
+                    (0, 2),
+                    (7, 6),
+                    (0, 3),
+                ],
+            );
+            assert_eq!(game.status(), GameStatus::InProgress);
+            play(&mut game, &[(7, 7)]);
+            assert_eq!(game.status(), GameStatus::BlackWon);
+            let result = game.result.as_ref().unwrap();
+            assert_eq!(result.reason, EndReason::Five);
+            assert_eq!(result.line, [(7, 3), (7, 4), (7, 5), (7, 6), (7, 7)]);
+        }
+
+        #[test]
+        fn diagonal_five_of_white_wins() {
+            let mut game = game_with(Opening::Free);
+            play(
+                &mut game,
+                &[
+                    (0, 14),
+                    (4, 4),
+                    (1, 14),
+                    (5, 5),
+                    (2, 14),
+                    (6, 6),
+                    (4, 14),
+                    (7, 7),
+                ],
+            );
+            play(&mut game, &[(5, 14), (3, 3)]);
+            assert_eq!(game.status(), GameStatus::WhiteWon);
+            assert_eq!(game.result.as_ref().unwrap().line.len(), 5);
+        }
+
+        #[test]
+        fn moves_alternate_starting_with_black() {
+            let mut game = game_with(Opening::Free);
+            assert_eq!(
+                game.put_piece(7, 7, PieceType::WHITE),
+                Err(MoveError::NotYourTurn)
+            );
+            game.put_piece(7, 7, PieceType::BLACK).unwrap();
+            assert_eq!(game.turn, PieceType::WHITE);
+            assert_eq!(
+                game.put_piece(7, 8, PieceType::BLACK),
+                Err(MoveError::NotYourTurn)
+            );
+            game.put_piece(7, 8, PieceType::WHITE).unwrap();
+            assert_eq!(game.turn, PieceType::BLACK);
+            assert_eq!(game.moves.len(), 2);
+        }
+
+        #[test]
+        fn illegal_moves_are_rejected() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7)]);
+            assert_eq!(
+                game.put_piece(7, 7, PieceType::WHITE),
+                Err(MoveError::Occupied)
+            );
+            assert_eq!(
+                game.put_piece(15, 0, PieceType::WHITE),
+                Err(MoveError::OutOfBounds)
+            );
+            assert_eq!(
+                game.put_piece(0, 15, PieceType::WHITE),
+                Err(MoveError::OutOfBounds)
+            );
+            assert_eq!(game.moves.len(), 1);
+            assert_eq!(game.turn, PieceType::WHITE);
+        }
+
+        #[test]
+        fn no_move_after_the_game_is_over() {
+            let mut game = game_with(Opening::Free);
+            play(
+                &mut game,
+                &[
+                    (7, 3),
+                    (0, 0),
+                    (7, 4),
+                    (0, 1),
+                    (7, 5),
+                    (0, 2),
+                    (7, 6),
+                    (0, 3),
+                    (7, 7),
+                ],
+            );
+            assert_eq!(
+                game.put_piece(0, 4, PieceType::WHITE),
+                Err(MoveError::GameOver)
+            );
+        }
+
+        #[test]
+        fn players_play_the_color_of_their_seat() {
+            let mut room = Room::new(1, String::new(), game_with(Opening::Free));
+            room.enter(1, String::new());
+            assert_eq!(
+                room.put_piece(1, 7, 7).unwrap_err().reason,
+                Some(MoveError::WaitingForOpponent.as_str())
+            );
+            room.enter(2, String::new());
+            room.enter(3, String::new());
+            assert_eq!(
+                room.put_piece(3, 7, 7).unwrap_err().reason,
+                Some(MoveError::NotAPlayer.as_str())
+            );
+            assert_eq!(
+                room.put_piece(2, 7, 7).unwrap_err().reason,
+                Some(MoveError::NotYourTurn.as_str())
+            );
+            assert_eq!(room.put_piece(1, 7, 7).unwrap(), PieceType::BLACK);
+            assert_eq!(room.put_piece(2, 7, 8).unwrap(), PieceType::WHITE);
+        }
+
+        #[test]
+        fn takeback_pops_the_requesters_last_move() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7)]);
+            game.request_undo(PieceType::BLACK).unwrap();
+            let undone = game.answer_undo(PieceType::WHITE, true).unwrap();
+            let undone = undone
+                .iter()
+                .map(|v| (v.row_i, v.col_j))
+                .collect::<Vec<_>>();
+            assert_eq!(undone, [(7, 7)]);
+            assert!(game.moves.is_empty());
+            assert_eq!(game.board.get(7, 7), PieceType::EMPTY);
+            assert_eq!(game.turn, PieceType::BLACK);
+        }
+
+        #[test]
+        fn takeback_after_the_reply_pops_both_moves() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7), (7, 8), (8, 8)]);
+            game.request_undo(PieceType::WHITE).unwrap();
+            let undone = game.answer_undo(PieceType::BLACK, true).unwrap();
+            let undone = undone
+                .iter()
+                .map(|v| (v.row_i, v.col_j, v.piece_type))
+                .collect::<Vec<_>>();
+            assert_eq!(undone, [(8, 8, PieceType::BLACK), (7, 8, PieceType::WHITE)]);
+            assert_eq!(game.moves.len(), 1);
+            assert_eq!(game.board.get(7, 8), PieceType::EMPTY);
+            assert_eq!(game.turn, PieceType::WHITE);
+        }
+
+        #[test]
+        fn declined_takeback_keeps_the_moves() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7), (7, 8)]);
+            game.request_undo(PieceType::WHITE).unwrap();
+            assert!(game
+                .answer_undo(PieceType::BLACK, false)
+                .unwrap()
+                .is_empty());
+            assert_eq!(game.moves.len(), 2);
+            assert_eq!(game.turn, PieceType::BLACK);
+            assert!(game.undo_requester.is_none());
+        }
+
+        #[test]
+        fn takeback_requests_are_checked() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7)]);
+            assert_eq!(
+                game.request_undo(PieceType::WHITE),
+                Err(MoveError::NothingToUndo)
+            );
+            assert_eq!(
+                game.answer_undo(PieceType::WHITE, true).err(),
+                Some(MoveError::NoUndoRequest)
+            );
+            game.request_undo(PieceType::BLACK).unwrap();
+            assert_eq!(
+                game.request_undo(PieceType::BLACK),
+                Err(MoveError::UndoPending)
+            );
+            assert_eq!(
+                game.answer_undo(PieceType::BLACK, true).err(),
+                Some(MoveError::NoUndoRequest)
+            );
+            // a move withdraws the request
+            play(&mut game, &[(7, 8)]);
+            assert_eq!(
+                game.answer_undo(PieceType::WHITE, true).err(),
+                Some(MoveError::NoUndoRequest)
+            );
+        }
+
+        #[test]
+        fn resigning_loses() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7)]);
+            game.resign(PieceType::WHITE).unwrap();
+            assert_eq!(game.status(), GameStatus::BlackWon);
+            assert_eq!(game.result.as_ref().unwrap().reason, EndReason::Resign);
+            assert_eq!(game.resign(PieceType::BLACK), Err(MoveError::GameOver));
+        }
+
+        #[test]
+        fn draw_needs_both_sides() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7)]);
+            assert_eq!(
+                game.accept_draw(PieceType::WHITE),
+                Err(MoveError::NoDrawOffer)
+            );
+            assert_eq!(game.offer_draw(PieceType::BLACK), Ok(false));
+            assert_eq!(
+                game.offer_draw(PieceType::BLACK),
+                Err(MoveError::DrawOfferPending)
+            );
+            assert_eq!(
+                game.accept_draw(PieceType::BLACK),
+                Err(MoveError::NoDrawOffer)
+            );
+            game.accept_draw(PieceType::WHITE).unwrap();
+            assert_eq!(game.status(), GameStatus::Draw);
+            assert_eq!(game.result.as_ref().unwrap().reason, EndReason::DrawAgreed);
+        }
+
+        #[test]
+        fn offering_a_draw_after_the_opponent_agrees() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7)]);
+            assert_eq!(game.offer_draw(PieceType::WHITE), Ok(false));
+            assert_eq!(game.offer_draw(PieceType::BLACK), Ok(true));
+            assert_eq!(game.status(), GameStatus::Draw);
+        }
+
+        #[test]
+        fn a_move_withdraws_the_draw_offer() {
+            let mut game = game_with(Opening::Free);
+            play(&mut game, &[(7, 7)]);
+            game.offer_draw(PieceType::BLACK).unwrap();
+            play(&mut game, &[(7, 8)]);
+            assert_eq!(
+                game.accept_draw(PieceType::WHITE),
+                Err(MoveError::NoDrawOffer)
+            );
+        }
+
+        #[test]
+        fn full_board_is_a_draw() {
+            let mut game = Game::new(&GameSettings {
+                row_size: 5,
+                col_size: 5,
+                win_length: 5,
+                ..GameSettings::default()
+            })
+            .unwrap();
+            // a checkerboard with (2, 2) and (0, 1) swapped, so no line of five has a single
+            // color
+            let (mut black, mut white) = (Vec::new(), Vec::new());
+            for row_i in 0..5 {
+                for col_j in 0..5 {
+                    let is_black =
+                        ((row_i + col_j) % 2 == 0) != [(2, 2), (0, 1)].contains(&(row_i, col_j));
+                    if is_black {
+                        black.push((row_i, col_j));
+                    } else {
+                        white.push((row_i, col_j));
+                    }
+                }
+            }
+            let mut points = Vec::new();
+            for (i, &point) in black.iter().enumerate() {
+                points.push(point);
+                points.extend(white.get(i));
+            }
+            play(&mut game, &points[..24]);
+            assert_eq!(game.status(), GameStatus::InProgress);
+            play(&mut game, &points[24..]);
+            assert_eq!(game.status(), GameStatus::Draw);
+            assert_eq!(game.result.as_ref().unwrap().reason, EndReason::BoardFull);
+        }
+
+        const THREE: [(usize, usize); 3] = [(7, 7), (7, 8), (8, 7)];
+
+        #[test]
+        fn swap_lets_player2_take_black() {
+            let mut game = game_with(Opening::Swap);
+            assert_eq!(
+                game.place_opening(PlayerType::PLAYER2, &THREE, None).err(),
+                Some(MoveError::NotYourTurn)
+            );
+            let placed = game
+                .place_opening(PlayerType::PLAYER1, &THREE, None)
+                .unwrap();
+            let colors = placed.iter().map(|v| v.piece_type).collect::<Vec<_>>();
+            assert_eq!(
+                colors,
+                [PieceType::BLACK, PieceType::WHITE, PieceType::BLACK]
+            );
+            assert_eq!(
+                game.choose_color(PlayerType::PLAYER1, PieceType::BLACK),
+                Err(MoveError::NotYourTurn)
+            );
+            game.choose_color(PlayerType::PLAYER2, PieceType::BLACK)
+                .unwrap();
+            assert!(game.swapped);
+            assert_eq!(game.color_of(PlayerType::PLAYER1), PieceType::WHITE);
+            assert_eq!(game.color_of(PlayerType::PLAYER2), PieceType::BLACK);
+            assert_eq!(game.phase, OpeningPhase::Done);
+            assert_eq!((game.turn, game.opening_len), (PieceType::WHITE, 3));
+        }
+
+        #[test]
+        fn swap_keeps_colors_when_player2_takes_white() {
+            let mut game = game_with(Opening::Swap);
+            game.place_opening(PlayerType::PLAYER1, &THREE, None)
+                .unwrap();
+            game.choose_color(PlayerType::PLAYER2, PieceType::WHITE)
+                .unwrap();
+            assert!(!game.swapped);
+            assert_eq!(game.color_of(PlayerType::PLAYER1), PieceType::BLACK);
+            assert_eq!(
+                game.place_opening(PlayerType::PLAYER1, &[(0, 0)], None)
+                    .err(),
+                Some(MoveError::WrongPhase)
+            );
+        }
+
+        #[test]
+        fn swap2_hands_the_choice_back_to_player1() {
+            let mut game = game_with(Opening::Swap2);
+            game.place_opening(PlayerType::PLAYER1, &THREE, None)
+                .unwrap();
+            assert_eq!(
+                game.place_opening(PlayerType::PLAYER2, &[(0, 0)], None)
+                    .err(),
+                Some(MoveError::WrongStoneCount)
+            );
+            game.place_opening(PlayerType::PLAYER2, &[(0, 0), (0, 2)], None)
+                .unwrap();
+            assert_eq!(game.moves.len(), 5);
+            assert_eq!(
+                game.choose_color(PlayerType::PLAYER2, PieceType::BLACK),
+                Err(MoveError::NotYourTurn)
+            );
+            game.choose_color(PlayerType::PLAYER1, PieceType::WHITE)
+                .unwrap();
+            assert!(game.swapped);
+            assert_eq!(game.phase, OpeningPhase::Done);
+            assert_eq!(game.turn, PieceType::WHITE);
+        }
+
+        #[test]
+        fn soosorv8_plays_the_chosen_fifth_move() {
+            let mut game = game_with(Opening::Soosorv8);
+            game.place_opening(PlayerType::PLAYER1, &THREE, None)
+                .unwrap();
+            // PLAYER2 takes black, so PLAYER1 places the 4th stone as white
+            game.choose_color(PlayerType::PLAYER2, PieceType::BLACK)
+                .unwrap();
+            assert_eq!(
+                game.place_opening(PlayerType::PLAYER2, &[(0, 0)], Some(2))
+                    .err(),
+                Some(MoveError::NotYourTurn)
+            );
+            assert_eq!(
+                game.place_opening(PlayerType::PLAYER1, &[(0, 0)], Some(9))
+                    .err(),
+                Some(MoveError::BadFifthCount)
+            );
+            game.place_opening(PlayerType::PLAYER1, &[(0, 0)], Some(2))
+                .unwrap();
+            // black swaps again, PLAYER1 holds black and proposes
+            game.choose_color(PlayerType::PLAYER2, PieceType::WHITE)
+                .unwrap();
+            assert!(!game.swapped);
+            assert_eq!(
+                game.propose_fifth(PlayerType::PLAYER1, &[(3, 3)]),
+                Err(MoveError::WrongStoneCount)
+            );
+            assert_eq!(
+                game.propose_fifth(PlayerType::PLAYER1, &[(3, 3), (3, 3)]),
+                Err(MoveError::DuplicatePoint)
+            );
+            game.propose_fifth(PlayerType::PLAYER1, &[(3, 3), (11, 11)])
+                .unwrap();
+            assert_eq!(
+                game.choose_fifth(PlayerType::PLAYER1, (3, 3)).err(),
+                Some(MoveError::NotYourTurn)
+            );
+            assert_eq!(
+                game.choose_fifth(PlayerType::PLAYER2, (4, 4)).err(),
+                Some(MoveError::NotAProposal)
+            );
+            let fifth = game.choose_fifth(PlayerType::PLAYER2, (11, 11)).unwrap();
+            assert_eq!(fifth.piece_type, PieceType::BLACK);
+            assert_eq!(game.phase, OpeningPhase::Done);
+            assert_eq!((game.turn, game.opening_len), (PieceType::WHITE, 5));
+        }
+
+        #[test]
+        fn opening_moves_can_not_be_taken_back() {
+            let mut game = game_with(Opening::Swap);
+            game.place_opening(PlayerType::PLAYER1, &THREE, None)
+                .unwrap();
+            assert_eq!(
+                game.put_piece(0, 0, PieceType::WHITE),
+                Err(MoveError::OpeningInProgress)
+            );
+            game.choose_color(PlayerType::PLAYER2, PieceType::WHITE)
+                .unwrap();
+            assert_eq!(
+                game.request_undo(PieceType::BLACK),
+                Err(MoveError::NothingToUndo)
+            );
+        }
+
+        /// a timed room with the opening, PLAYER1 is 1 and PLAYER2 is 2 once both entered
+        fn timed_room(opening: Opening) -> Room {
+            let settings = GameSettings {
+                opening,
+                time_control: Some(TimeControl {
+                    main_secs: 60,
+                    increment_secs: 0,
+                    periods: 0,
+                    period_secs: 0,
+                }),
+                ..GameSettings::default()
+            };
+            Room::new(1, String::new(), Game::new(&settings).unwrap())
+        }
+
+        fn running(room: &Room) -> PieceType {
+            room.clocks().unwrap().running
+        }
+
+        #[test]
+        fn clock_runs_for_the_first_move_once_both_seats_are_taken() {
+            let mut room = timed_room(Opening::Free);
+            room.enter(1, String::new());
+            assert_eq!(running(&room), PieceType::EMPTY);
+            room.enter(2, String::new());
+            assert_eq!(running(&room), PieceType::BLACK);
+            assert!(room.deadline().is_some());
+            room.put_piece(1, 7, 7).unwrap();
+            assert_eq!(running(&room), PieceType::WHITE);
+        }
+
+        #[test]
+        fn clock_stops_when_a_seat_is_left_before_the_first_move() {
+            let mut room = timed_room(Opening::Free);
+            room.enter(1, String::new());
+            room.enter(2, String::new());
+            room.exit(2).unwrap();
+            assert_eq!(running(&room), PieceType::EMPTY);
+            assert_eq!(room.deadline(), None);
+            room.enter(3, String::new());
+            assert_eq!(running(&room), PieceType::BLACK);
+        }
+
+        #[test]
+        fn clock_runs_for_the_actor_of_the_opening() {
+            let mut room = timed_room(Opening::Swap);
+            room.enter(1, String::new());
+            room.enter(2, String::new());
+            // PLAYER1 places the first three stones as black
+            assert_eq!(running(&room), PieceType::BLACK);
+            room.place_opening(1, &[(7, 7), (7, 8), (8, 7)], None)
+                .unwrap();
+            // PLAYER2 chooses while holding white
+            assert_eq!(running(&room), PieceType::WHITE);
+            room.choose_color(2, PieceType::BLACK).unwrap();
+            // white is to move, now held by PLAYER1
+            assert_eq!(running(&room), PieceType::WHITE);
+            assert_eq!(room.put_piece(1, 0, 0).unwrap(), PieceType::WHITE);
+            assert_eq!(running(&room), PieceType::BLACK);
+        }
+
+        #[test]
+        fn clock_restarts_after_a_reset() {
+            let mut room = timed_room(Opening::Free);
+            room.enter(1, String::new());
+            room.enter(2, String::new());
+            room.put_piece(1, 7, 7).unwrap();
+            room.put_piece(2, 7, 8).unwrap();
+            assert!(!room.reset_game(1).unwrap());
+            assert!(room.reset_game(2).unwrap());
+            assert_eq!(running(&room), PieceType::BLACK);
         }
     }
 }
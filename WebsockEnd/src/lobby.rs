@@ -0,0 +1,481 @@
+//! Task of the lobby.
+//!
+//! The lobby owns the [`Context`]: who is connected and which rooms exist. It hands out
+//! [`RoomHandle`]s, after that connections talk to rooms directly.
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::bot::BotPool;
+use crate::error::Error;
+use crate::gomoku_ol::{Context, GameRecord, Match};
+use crate::protocol::{Event, GameSettings, Request, RoomBrief, RoomInfo};
+use crate::room::RoomHandle;
+use crate::store::Store;
+use crate::{ClientCloser, ClientSender, Reply, RoomSink};
+
+/// how often the queue is searched for players whose rating windows meet
+const MATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// id of a resumed player and the rooms it is in
+pub type Resumed = (i32, Vec<(i32, RoomHandle)>);
+
+/// message to the task of the lobby
+enum LobbyMsg {
+    AddPlayer {
+        ip_addr: String,
+        sender: ClientSender,
+        closer: ClientCloser,
+        reply: oneshot::Sender<(i32, String)>,
+    },
+    RemovePlayer {
+        player_id: i32,
+    },
+    /// the connection behind sender dropped
+    Disconnect {
+        player_id: i32,
+        sender: ClientSender,
+    },
+    Resume {
+        token: String,
+        sender: ClientSender,
+        closer: ClientCloser,
+        reply: oneshot::Sender<Result<Resumed, Error>>,
+    },
+    Nickname {
+        player_id: i32,
+        reply: oneshot::Sender<Result<String, Error>>,
+    },
+    SetNickname {
+        player_id: i32,
+        nickname: String,
+        reply: oneshot::Sender<Result<String, Error>>,
+    },
+    Register {
+        player_id: i32,
+        username: String,
+        password_hash: String,
+        reply: oneshot::Sender<Result<String, Error>>,
+    },
+    FindAccount {
+        username: String,
+        reply: oneshot::Sender<Result<(i64, String), Error>>,
+    },
+    Login {
+        player_id: i32,
+        account_id: i64,
+        reply: oneshot::Sender<Result<String, Error>>,
+    },
+    JoinQueue {
+        player_id: i32,
+        settings: GameSettings,
+        rooms: RoomSink,
+        reply: Reply,
+    },
+    LeaveQueue {
+        player_id: i32,
+        reply: Reply,
+    },
+    /// sent by a room when the game in it started, with the seated players
+    GameStarted {
+        room_id: i32,
+        players: Vec<i32>,
+    },
+    /// sent by a room when a game in it ended
+    GameFinished(GameRecord),
+    Request {
+        request: Request,
+        reply: Reply,
+    },
+    FindRoom {
+        room_id: i32,
+        reply: oneshot::Sender<Result<RoomHandle, Error>>,
+    },
+    /// sent by a room after it changed
+    RoomChanged(RoomInfo),
+}
+
+/// handle to talk to the task of the lobby
+#[derive(Clone)]
+pub struct LobbyHandle {
+    sender: mpsc::UnboundedSender<LobbyMsg>,
+}
+impl LobbyHandle {
+    /// start the lobby with the accounts of store, rooms it creates hold the seat of a
+    /// player who lost connection for grace_period and have their bots search on bots.
+    /// reports is another connection to the database, the leaderboard and player statistics
+    /// are read through it on blocking threads
+    pub fn spawn(
+        store: Store,
+        reports: Store,
+        grace_period: Duration,
+        bots: BotPool,
+    ) -> LobbyHandle {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let lobby = LobbyHandle { sender };
+        tokio::spawn(run(
+            Context::new(store),
+            Arc::new(Mutex::new(reports)),
+            lobby.clone(),
+            receiver,
+            grace_period,
+            bots,
+        ));
+        lobby
+    }
+
+    /// add a player whose lobby events are sent to sender, return its id and session token.
+    /// closer closes the connection once another one resumes as the player
+    pub async fn add_player(
+        &self,
+        ip_addr: &str,
+        sender: ClientSender,
+        closer: ClientCloser,
+    ) -> Result<(i32, String), Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::AddPlayer {
+            ip_addr: ip_addr.to_string(),
+            sender,
+            closer,
+            reply,
+        })?;
+        result.await.map_err(|_| Error::internal())
+    }
+
+    pub fn remove_player(&self, player_id: i32) {
+        self.send(LobbyMsg::RemovePlayer { player_id })
+            .unwrap_or_default();
+    }
+
+    /// the connection behind sender dropped, the player can `resume` for the grace period
+    pub fn disconnect(&self, player_id: i32, sender: ClientSender) {
+        self.send(LobbyMsg::Disconnect { player_id, sender })
+            .unwrap_or_default();
+    }
+
+    /// send the events of the player token belongs to to sender from now on, return its id
+    /// and the rooms it is in. A connection still serving the player is closed
+    pub async fn resume(
+        &self,
+        token: &str,
+        sender: ClientSender,
+        closer: ClientCloser,
+    ) -> Result<Resumed, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::Resume {
+            token: token.to_string(),
+            sender,
+            closer,
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    pub async fn nickname(&self, player_id: i32) -> Result<String, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::Nickname { player_id, reply })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// change the nickname of the player, return it as it is stored
+    pub async fn set_nickname(&self, player_id: i32, nickname: &str) -> Result<String, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::SetNickname {
+            player_id,
+            nickname: nickname.to_string(),
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// create an account with the hash of its password and log the player in to it,
+    /// return its username
+    pub async fn register(
+        &self,
+        player_id: i32,
+        username: &str,
+        password_hash: String,
+    ) -> Result<String, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::Register {
+            player_id,
+            username: username.to_string(),
+            password_hash,
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// id and password hash of the account called username
+    pub async fn find_account(&self, username: &str) -> Result<(i64, String), Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::FindAccount {
+            username: username.to_string(),
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// log the player in to the account whose password it has given, return the username
+    pub async fn login(&self, player_id: i32, account_id: i64) -> Result<String, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::Login {
+            player_id,
+            account_id,
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// wait for an opponent asking for the same settings, the room both are seated in is
+    /// sent to rooms
+    pub async fn join_queue(
+        &self,
+        player_id: i32,
+        settings: GameSettings,
+        rooms: RoomSink,
+    ) -> Result<Value, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::JoinQueue {
+            player_id,
+            settings,
+            rooms,
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// leave the queue, once this returns the player is not seated by it any more
+    pub async fn leave_queue(&self, player_id: i32) -> Result<Value, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::LeaveQueue { player_id, reply })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    pub fn game_started(&self, room_id: i32, players: Vec<i32>) {
+        self.send(LobbyMsg::GameStarted { room_id, players })
+            .unwrap_or_default();
+    }
+
+    pub fn game_finished(&self, record: GameRecord) {
+        self.send(LobbyMsg::GameFinished(record))
+            .unwrap_or_default();
+    }
+
+    /// handle a request not about a single room
+    pub async fn request(&self, request: Request) -> Result<Value, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::Request { request, reply })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    pub async fn find_room(&self, room_id: i32) -> Result<RoomHandle, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(LobbyMsg::FindRoom { room_id, reply })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// keep the room list up to date with info
+    pub fn room_changed(&self, info: RoomInfo) {
+        self.send(LobbyMsg::RoomChanged(info)).unwrap_or_default();
+    }
+
+    fn send(&self, msg: LobbyMsg) -> Result<(), Error> {
+        self.sender.send(msg).map_err(|_| Error::internal())
+    }
+}
+
+async fn run(
+    mut context: Context,
+    reports: Arc<Mutex<Store>>,
+    lobby: LobbyHandle,
+    mut receiver: mpsc::UnboundedReceiver<LobbyMsg>,
+    grace_period: Duration,
+    bots: BotPool,
+) {
+    let mut match_interval = tokio::time::interval(MATCH_INTERVAL);
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = match_interval.tick() => {
+                start_matches(&mut context, &lobby, grace_period, &bots);
+                continue;
+            }
+        };
+        match msg {
+            LobbyMsg::AddPlayer {
+                ip_addr,
+                sender,
+                closer,
+                reply,
+            } => {
+                reply
+                    .send(context.add_player(&ip_addr, sender, closer))
+                    .unwrap_or_default();
+            }
+            LobbyMsg::RemovePlayer { player_id } => context.remove_player(player_id),
+            LobbyMsg::Disconnect { player_id, sender } => {
+                context.disconnect(player_id, &sender, grace_period)
+            }
+            LobbyMsg::Resume {
+                token,
+                sender,
+                closer,
+                reply,
+            } => {
+                reply
+                    .send(context.resume(&token, sender, closer))
+                    .unwrap_or_default();
+            }
+            LobbyMsg::Request {
+                request: request @ (Request::Leaderboard { .. } | Request::PlayerStats { .. }),
+                reply,
+            } => {
+                let reports = reports.clone();
+                tokio::task::spawn_blocking(move || {
+                    let result = report(&reports.lock().unwrap(), request);
+                    reply.send(result).unwrap_or_default();
+                });
+            }
+            LobbyMsg::Request { request, reply } => {
+                let result = handle_request(&mut context, &lobby, request, grace_period, &bots);
+                reply.send(result).unwrap_or_default();
+            }
+            LobbyMsg::FindRoom { room_id, reply } => {
+                reply.send(context.room(room_id)).unwrap_or_default();
+            }
+            LobbyMsg::Nickname { player_id, reply } => {
+                reply.send(context.nickname(player_id)).unwrap_or_default();
+            }
+            LobbyMsg::SetNickname {
+                player_id,
+                nickname,
+                reply,
+            } => {
+                reply
+                    .send(context.set_nickname(player_id, &nickname))
+                    .unwrap_or_default();
+            }
+            LobbyMsg::Register {
+                player_id,
+                username,
+                password_hash,
+                reply,
+            } => {
+                reply
+                    .send(context.register(player_id, &username, &password_hash))
+                    .unwrap_or_default();
+            }
+            LobbyMsg::FindAccount { username, reply } => {
+                reply
+                    .send(context.find_account(&username))
+                    .unwrap_or_default();
+            }
+            LobbyMsg::Login {
+                player_id,
+                account_id,
+                reply,
+            } => {
+                reply
+                    .send(context.login(player_id, account_id))
+                    .unwrap_or_default();
+            }
+            LobbyMsg::JoinQueue {
+                player_id,
+                settings,
+                rooms,
+                reply,
+            } => {
+                let result = context.join_queue(player_id, settings, rooms);
+                reply.send(result.map(|_| json!({}))).unwrap_or_default();
+                start_matches(&mut context, &lobby, grace_period, &bots);
+            }
+            LobbyMsg::LeaveQueue { player_id, reply } => {
+                let result = context.leave_queue(player_id);
+                reply.send(result.map(|_| json!({}))).unwrap_or_default();
+            }
+            LobbyMsg::GameStarted { room_id, players } => context.game_started(room_id, &players),
+            LobbyMsg::GameFinished(record) => context.game_finished(&record),
+            LobbyMsg::RoomChanged(info) => context.update_room(info),
+        }
+    }
+}
+
+fn handle_request(
+    context: &mut Context,
+    lobby: &LobbyHandle,
+    request: Request,
+    grace_period: Duration,
+    bots: &BotPool,
+) -> Result<Value, Error> {
+    match request {
+        Request::CreateRoom { name, settings } => {
+            let room = context.new_room(name, &settings)?;
+            let info = room.info();
+            let brief = RoomBrief {
+                id: info.id,
+                name: info.name.clone(),
+            };
+            let handle = RoomHandle::spawn(
+                room,
+                HashMap::new(),
+                lobby.clone(),
+                grace_period,
+                bots.clone(),
+            );
+            context.add_room(handle, info, false);
+            let event = Event::RoomList(context.room_list());
+            context.broadcast(&event);
+            Ok(json!({ "room": brief }))
+        }
+        Request::RoomList => Ok(json!(context.room_list())),
+        Request::Profile { player_id } => Ok(json!(context.profile(player_id)?)),
+        // requests about a room go to the room
+        _ => Err(Error::internal()),
+    }
+}
+
+/// answer a request that only reads the database
+fn report(reports: &Store, request: Request) -> Result<Value, Error> {
+    match request {
+        Request::Leaderboard { offset, limit } => Ok(json!(reports.leaderboard(offset, limit)?)),
+        Request::PlayerStats { username } => Ok(json!(reports.player_stats(&username)?)),
+        _ => Err(Error::internal()),
+    }
+}
+
+/// start the rooms of the players the queue paired and tell them
+fn start_matches(
+    context: &mut Context,
+    lobby: &LobbyHandle,
+    grace_period: Duration,
+    bots: &BotPool,
+) {
+    for Match { room, players } in context.take_matches(Instant::now()) {
+        let info = room.info();
+        let room_id = info.id;
+        let state = room.state();
+        let seq = state.seq;
+        let msg = Event::MatchFound {
+            room_id,
+            room: Box::new(state),
+        }
+        .to_json(seq);
+        let members = players
+            .iter()
+            .map(|(player_id, sender, _)| (*player_id, sender.clone()))
+            .collect();
+        let handle = RoomHandle::spawn(room, members, lobby.clone(), grace_period, bots.clone());
+        context.add_room(handle.clone(), info, true);
+        for (_, sender, rooms) in players {
+            // ignore failure, connections leave the queue before they close
+            rooms.send((room_id, handle.clone())).unwrap_or_default();
+            sender.send(msg.clone()).unwrap_or_default();
+        }
+    }
+}
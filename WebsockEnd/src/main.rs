@@ -1,374 +1,413 @@
-use gomoku_game_websocket::gomoku_ol::{Context, PieceType};
-use std::str;
+use futures_util::{SinkExt, StreamExt};
+use gomoku_game_websocket::bot::BotPool;
+use gomoku_game_websocket::error::{Error, ErrorCode};
+use gomoku_game_websocket::lobby::LobbyHandle;
+use gomoku_game_websocket::protocol::{Incoming, Request, Response};
+use gomoku_game_websocket::room::RoomHandle;
+use gomoku_game_websocket::store::{self, Store};
+use gomoku_game_websocket::{ClientCloser, ClientSender, RoomSink};
+use serde_json::{json, Value};
 use std::{
-    collections::{HashMap, VecDeque},
-    net::TcpStream,
-    sync::{
-        mpsc::{self, Receiver, Sender},
-        Arc, Mutex,
-    },
-    thread, usize,
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
 };
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
 
-use websocket::sync::Server;
-use websocket::{sync::Client, Message};
+/// requests a client can send per second on average
+const REQUESTS_PER_SEC: f64 = 10.0;
+/// requests a client can send at once after being idle
+const REQUESTS_BURST: f64 = 20.0;
+/// seconds the seat of a player who lost connection is held, unless `--grace-secs` says otherwise
+const DEFAULT_GRACE_SECS: u64 = 30;
+/// database of accounts, unless `--db` says otherwise
+const DEFAULT_DB_PATH: &str = "gomoku.db";
 
-enum ThreadJobCmd {
-    SendData,
+/// token bucket limiting how fast a client can send requests
+struct RateLimiter {
+    tokens: f64,
+    last_refill: Instant,
 }
-struct ThreadJob {
-    job_cmd: ThreadJobCmd,
-    data: String,
+impl RateLimiter {
+    fn new() -> RateLimiter {
+        RateLimiter {
+            tokens: REQUESTS_BURST,
+            last_refill: Instant::now(),
+        }
+    }
+    /// take a token if there is any
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * REQUESTS_PER_SEC).min(REQUESTS_BURST);
+        self.last_refill = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
 }
-impl ThreadJob {
-    fn new(job_cmd: ThreadJobCmd, data: &String) -> ThreadJob {
-        ThreadJob {
-            job_cmd,
-            data: data.clone(),
+
+/// rebind the connection to the player token was issued to, it takes over the rooms that
+/// player is in and player_id becomes its id. closer closes this connection in turn
+async fn resume(
+    lobby: &LobbyHandle,
+    rooms: &mut HashMap<i32, RoomHandle>,
+    sender: &ClientSender,
+    closer: &ClientCloser,
+    player_id: &mut i32,
+    token: &str,
+) -> Result<Value, Error> {
+    let (resumed_id, resumed_rooms) = lobby.resume(token, sender.clone(), closer.clone()).await?;
+    if resumed_id != *player_id {
+        rooms
+            .values()
+            .for_each(|room| room.leave(*player_id, sender.clone()));
+        lobby.remove_player(*player_id);
+        *player_id = resumed_id;
+    }
+    rooms.clear();
+    for (room_id, room) in resumed_rooms {
+        // ignore failure, the room has let the player go meanwhile
+        if room.rejoin(resumed_id, sender.clone()).await.is_ok() {
+            rooms.insert(room_id, room);
         }
     }
+    let mut room_ids = rooms.keys().copied().collect::<Vec<i32>>();
+    room_ids.sort_unstable();
+    Ok(json!({ "id": resumed_id, "rooms": room_ids }))
 }
 
-fn format_res(data: Option<String>, resp_for: &str) -> String {
-    format!(
-        "{{\"ok\":{},\"type\":\"{}\",\"data\":{}}}",
-        data.is_some(),
-        resp_for,
-        data.unwrap_or("\"no data\"".to_string())
-    )
+/// tell the rooms of player_id it is called nickname now
+fn renamed(rooms: &HashMap<i32, RoomHandle>, player_id: i32, nickname: String) -> Value {
+    rooms
+        .values()
+        .for_each(|room| room.rename(player_id, &nickname));
+    json!({ "nickname": nickname })
 }
 
-/// handle message of a client, return -1 if any send or recv failed
-fn handle_message(
-    context: &Arc<Mutex<Context>>,
-    channels_map: &Arc<Mutex<HashMap<i32, Sender<ThreadJob>>>>,
-    ws_client: &mut Client<TcpStream>,
-    msg: &String,
-) -> i32 {
-    let lines: Vec<&str> = msg.split('\n').collect();
-    if lines.len() == 0 {
-        match ws_client.send_message(&Message::text(format_res(
-            Some("Invalid Data".to_string()),
-            "invalid data",
-        ))) {
-            Ok(_) => {}
-            Err(_) => {
-                eprintln!("send INVALID_DATA msg failed");
-                return -1;
-            }
-        };
-        return 0;
-    }
+/// create an account and log player_id in to it. Hashing takes a while, so it is done on a
+/// blocking thread
+async fn register(
+    lobby: &LobbyHandle,
+    rooms: &HashMap<i32, RoomHandle>,
+    player_id: i32,
+    username: &str,
+    password: &str,
+) -> Result<Value, Error> {
+    store::check_password(password)?;
+    let password = password.to_string();
+    let password_hash = tokio::task::spawn_blocking(move || store::hash_password(&password))
+        .await
+        .map_err(|_| Error::internal())??;
+    let nickname = lobby.register(player_id, username, password_hash).await?;
+    Ok(renamed(rooms, player_id, nickname))
+}
 
-    let mut players_to_resp: Vec<i32> = Vec::new();
-    let mut resp_msg_to_all_player: Option<String> = None;
-    let mut resp_msg: Option<String> = None;
+/// log player_id in to the account called username if password is right
+async fn login(
+    lobby: &LobbyHandle,
+    rooms: &HashMap<i32, RoomHandle>,
+    player_id: i32,
+    username: &str,
+    password: &str,
+) -> Result<Value, Error> {
+    let (account_id, password_hash) = lobby.find_account(username).await?;
+    let password = password.to_string();
+    let verified =
+        tokio::task::spawn_blocking(move || store::verify_password(&password, &password_hash))
+            .await
+            .unwrap_or(false);
+    if !verified {
+        return Err(Error::login_failed());
+    }
+    let nickname = lobby.login(player_id, account_id).await?;
+    Ok(renamed(rooms, player_id, nickname))
+}
 
-    match lines[0] {
-        "create_room" if lines.len() == 2 => {
-            let room_name = lines[1];
-            let mut tmp_context = context.lock().unwrap();
-            resp_msg = Some(tmp_context.create_room(room_name.to_string()));
-            resp_msg_to_all_player = Some(format!(
-                "{{\"msg_others\":\"room_list\",\"data\":{}}}",
-                tmp_context.room_list_json().unwrap()
-            ));
-            players_to_resp = tmp_context.all_players();
+/// send request of player_id to the lobby or the room it is about.
+/// rooms holds the handles of the rooms the player is in, so they are left on disconnect,
+/// rooms the queue seats the player in are sent to room_sink
+async fn handle_request(
+    lobby: &LobbyHandle,
+    rooms: &mut HashMap<i32, RoomHandle>,
+    sender: &ClientSender,
+    closer: &ClientCloser,
+    room_sink: &RoomSink,
+    player_id: &mut i32,
+    request: Request,
+) -> Result<Value, Error> {
+    match request {
+        Request::JoinQueue { settings } => {
+            return lobby
+                .join_queue(*player_id, settings, room_sink.clone())
+                .await
         }
-        "room_list" => {
-            resp_msg = context.lock().unwrap().room_list_json();
+        Request::LeaveQueue => return lobby.leave_queue(*player_id).await,
+        _ => {}
+    }
+    match &request {
+        Request::Resume { token } => {
+            return resume(lobby, rooms, sender, closer, player_id, token).await
         }
-        "enter_room" if lines.len() == 3 => {
-            let player_id = lines[1].parse::<i32>();
-            let room_id = lines[2].parse::<i32>();
-            if player_id.is_ok() && room_id.is_ok() {
-                let player_id = player_id.unwrap();
-                let room_id = room_id.unwrap();
-                let mut tmp_context = context.lock().unwrap();
-                resp_msg = tmp_context.player_enter_room(player_id, room_id);
-                if resp_msg.is_some() {
-                    players_to_resp = tmp_context.players_of_room(room_id).unwrap_or_default();
-                    resp_msg_to_all_player = Some(format!(
-                        "{{\"msg_others\":\"enter_room\",\"data\":{{\"room_id\":{},\"player_id\":{},\"player_type\":{}}}}}",
-                        room_id, player_id, tmp_context.type_of_player(player_id, room_id).unwrap()
-                    ));
-                }
-            }
+        Request::SetNickname { nickname } => {
+            let nickname = lobby.set_nickname(*player_id, nickname).await?;
+            return Ok(renamed(rooms, *player_id, nickname));
         }
-        "exit_room" if lines.len() == 3 => {
-            let player_id = lines[1].parse::<i32>();
-            let room_id = lines[2].parse::<i32>();
-            if player_id.is_ok() && room_id.is_ok() {
-                let player_id = player_id.unwrap();
-                let room_id = room_id.unwrap();
-                let mut tmp_context = context.lock().unwrap();
-                let player_type = tmp_context.type_of_player(player_id, room_id);
-                resp_msg = tmp_context.player_exit_room(player_id, room_id);
-                if resp_msg.is_some() {
-                    players_to_resp = tmp_context.players_of_room(room_id).unwrap_or_default();
-                    resp_msg_to_all_player = Some(format!(
-                        "{{\"msg_others\":\"exit_room\",\"data\":{{\"room_id\":{},\"player_id\":{},\"player_type\":{}}}}}",
-                        room_id, player_id,player_type.unwrap()
-                    ));
-                }
-            }
+        Request::Register { username, password } => {
+            return register(lobby, rooms, *player_id, username, password).await
         }
-        "reset_game" => {
-            if lines.len() == 2 {
-                let room_id = lines[1].parse::<i32>();
-                if room_id.is_ok() {
-                    let room_id = room_id.unwrap();
-                    let mut tmp_context = context.lock().unwrap();
-                    resp_msg = tmp_context.reset_game(room_id);
-                    if resp_msg.is_some() {
-                        players_to_resp = tmp_context.players_of_room(room_id).unwrap_or_default();
-                        resp_msg_to_all_player = Some(String::from("{\"msg_others\":\"reset\"}"));
-                    }
-                }
-            }
+        Request::Login { username, password } => {
+            return login(lobby, rooms, *player_id, username, password).await
         }
-        "put_piece" => {
-            if lines.len() == 5 {
-                let room_id = lines[1].parse::<i32>();
-                let row_i = lines[2].parse::<usize>();
-                let col_j = lines[3].parse::<usize>();
-                let piece_type = lines[4].parse::<i32>();
-                if room_id.is_ok() && row_i.is_ok() && col_j.is_ok() && piece_type.is_ok() {
-                    let room_id = room_id.unwrap();
-                    let row_i = row_i.unwrap();
-                    let col_j = col_j.unwrap();
-                    let piece_type = PieceType::from_i32(piece_type.unwrap());
-                    let mut tmp_context = context.lock().unwrap();
-                    resp_msg = tmp_context.put_piece(room_id, row_i, col_j, piece_type);
-                    if resp_msg.is_some() {
-                        players_to_resp = tmp_context.players_of_room(room_id).unwrap_or_default();
-                        resp_msg_to_all_player = Some(format!(
-                            "{{\"msg_others\":\"put_piece\",\"data\":{{\"room_id\":{},\"row_i\":{},\"col_j\":{},\"piece_type\":{}}}}}",
-                            room_id,
-                            row_i,
-                            col_j,
-                            piece_type.to_i32()
-                        ));
-                    }
-                }
-            }
+        _ => {}
+    }
+    let player_id = *player_id;
+    let room_id = match request.room_id() {
+        Some(v) => v,
+        None => return lobby.request(request).await,
+    };
+    let room = match rooms.get(&room_id) {
+        Some(room) => room.clone(),
+        None => lobby.find_room(room_id).await?,
+    };
+    match request {
+        Request::EnterRoom { .. } => {
+            let nickname = lobby.nickname(player_id).await?;
+            let room_state = room.enter(player_id, nickname, sender.clone()).await?;
+            rooms.insert(room_id, room);
+            Ok(room_state)
         }
-        // "unput_piece" => {},
-        _ => {
-            resp_msg = Some("\"data\":\"unknown message\"".to_string());
+        Request::ExitRoom { .. } => {
+            let result = room.request(player_id, request).await?;
+            rooms.remove(&room_id);
+            Ok(result)
         }
-    };
-    let resp_msg = format_res(resp_msg, lines[0]);
-
-    if resp_msg_to_all_player.is_some() {
-        let msg = resp_msg_to_all_player.unwrap();
-        players_to_resp.iter().for_each(|v| {
-            // ignore failure
-            match channels_map.lock().unwrap().get_mut(v) {
-                Some(sender) => {
-                    sender
-                        .send(ThreadJob::new(ThreadJobCmd::SendData, &msg))
-                        .unwrap_or_default();
-                }
-                // ignore
-                None => {}
-            }
-        });
+        request => room.request(player_id, request).await,
     }
+}
 
-    loop {
-        match ws_client.send_message(&Message::text(&resp_msg)) {
-            Ok(_) => {
-                break;
-            }
-            Err(err) => match err {
-                websocket::WebSocketError::IoError(v) => match v.kind() {
-                    std::io::ErrorKind::WouldBlock => {
-                        continue;
-                    }
-                    _ => {
-                        eprintln!("send response msg failed:{}", v);
-                        return -1;
-                    }
-                },
-                _ => {
-                    eprintln!("send response msg failed: {}", err);
-                    return -1;
-                }
-            },
+/// handle message of client player_id, return the response to it.
+/// Messages not starting with `{` are parsed as the legacy newline separated format if
+/// legacy_protocol is enabled
+#[allow(clippy::too_many_arguments)]
+async fn handle_message(
+    lobby: &LobbyHandle,
+    rooms: &mut HashMap<i32, RoomHandle>,
+    sender: &ClientSender,
+    closer: &ClientCloser,
+    room_sink: &RoomSink,
+    player_id: &mut i32,
+    msg: &str,
+    legacy_protocol: bool,
+    rate_limiter: &mut RateLimiter,
+) -> String {
+    let Incoming {
+        resp_for,
+        req_id,
+        mut request,
+    } = Incoming::parse(msg, *player_id, legacy_protocol);
+    if !rate_limiter.allow() {
+        request = Err(Error::new(
+            ErrorCode::RateLimited,
+            "too many requests, slow down",
+        ));
+    }
+    let result = match request {
+        Ok(request) => {
+            handle_request(lobby, rooms, sender, closer, room_sink, player_id, request).await
         }
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(data) => Response::ok(&resp_for, data),
+        Err(err) => Response::err(&resp_for, &err),
     }
-    return 0;
+    .with_req_id(req_id)
+    .to_json()
 }
 
-fn main() {
-    let port = 8686;
-    let ws_server = Server::bind(format!("0.0.0.0:{}", port))
-        .expect(&format!("bind websocket to port {} failed", port));
-
-    let context = Arc::new(Mutex::new(Context::new()));
-    let channels_map: Arc<Mutex<HashMap<i32, Sender<ThreadJob>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-
-    for connection in ws_server.filter_map(Result::ok) {
-        let cloned_context = Arc::clone(&context);
-        let cloned_channels_map = Arc::clone(&channels_map);
-        thread::spawn(move || {
-            let ws_client = connection.accept();
-            if ws_client.is_err() {
-                eprintln!("accept failed");
-                return;
-            }
-            let mut ws_client = ws_client.unwrap();
-            let peer_ip_addr = ws_client.peer_addr().unwrap().to_string();
-            eprintln!("connection to {} established", &peer_ip_addr);
-
-            // add to player list
-            let player_id: i32 = { cloned_context.lock().unwrap().add_player(&peer_ip_addr) };
-            // create a channel
-            let receiver: Receiver<ThreadJob>;
-            let (tx, rx) = mpsc::channel::<ThreadJob>();
-            receiver = rx;
-            {
-                cloned_channels_map.lock().unwrap().insert(player_id, tx);
-            }
-            let shut_down =
-                |ws_client: &Client<TcpStream>,
-                 cloned_channels_map: &Arc<Mutex<HashMap<i32, Sender<ThreadJob>>>>| {
-                    // remove channel
-                    cloned_channels_map
-                        .lock()
-                        .unwrap()
-                        .remove(&player_id)
-                        .unwrap();
-                    // shutdown
-                    ws_client.shutdown().unwrap_or(());
-                    cloned_context.lock().unwrap().remove_player(player_id);
-                };
-
-            // send id
-            match ws_client.send_message(&Message::text(format_res(
-                Some(format!("{{\"id\":{}}}", player_id)),
-                "your_id",
-            ))) {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("send player id failed: {}", err);
-                    shut_down(&ws_client, &cloned_channels_map);
-                    return;
-                }
-            }
-
-            // set non-blocking
-            if ws_client.set_nonblocking(true).is_err() {
-                eprintln!("set non-blocking failed");
-                return;
-            }
+/// serve a client till it disconnects, the task only wakes up when the client sends
+/// something or there is something to send to it
+async fn serve_client(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    lobby: LobbyHandle,
+    legacy_protocol: bool,
+) {
+    let ws_stream = match tokio_tungstenite::accept_async(stream).await {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("accept {} failed: {}", peer_addr, err);
+            return;
+        }
+    };
+    let peer_ip_addr = peer_addr.to_string();
+    eprintln!("connection to {} established", &peer_ip_addr);
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-            let mut job_queue: VecDeque<ThreadJob> = VecDeque::new();
-            let mut client_failure_or_closed = false;
+    // create a channel for lobby and rooms to send to the client
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    // and one for the lobby to close it when another connection resumes as the player
+    let (closer, mut closed) = mpsc::unbounded_channel::<()>();
+    // add to player list
+    let added = lobby
+        .add_player(&peer_ip_addr, sender.clone(), closer.clone())
+        .await;
+    let (mut player_id, token) = match added {
+        Ok(v) => v,
+        Err(err) => {
+            eprintln!("add player {} failed: {}", peer_addr, err.message);
+            return;
+        }
+    };
+    // rooms the player is in
+    let mut rooms: HashMap<i32, RoomHandle> = HashMap::new();
+    // rooms the queue seated the player in
+    let (room_sink, mut room_receiver) = mpsc::unbounded_channel::<(i32, RoomHandle)>();
 
-            loop {
+    // send id, with the token to resume as this player after reconnecting
+    let your_id = Response::ok("your_id", json!({ "id": player_id, "token": token })).to_json();
+    if let Err(err) = ws_sender.send(Message::Text(your_id)).await {
+        eprintln!("send player id failed: {}", err);
+    } else {
+        let mut rate_limiter = RateLimiter::new();
+        loop {
+            tokio::select! {
                 // receive clients msg
-                match ws_client.recv_message() {
-                    Ok(v) => match v {
-                        websocket::OwnedMessage::Text(msg) => {
-                            let handle_res = handle_message(
-                                &cloned_context,
-                                &cloned_channels_map,
-                                &mut ws_client,
-                                &msg,
-                            );
-                            if handle_res == -1 {
-                                eprintln!("handle message of client {} failed", &peer_ip_addr);
-                                client_failure_or_closed = true;
-                            }
-                        }
-                        websocket::OwnedMessage::Close(_) => {
-                            eprintln!("client closing connection");
-                            client_failure_or_closed = true;
+                msg = ws_receiver.next() => match msg {
+                    Some(Ok(Message::Text(msg))) => {
+                        let resp_msg = handle_message(
+                            &lobby,
+                            &mut rooms,
+                            &sender,
+                            &closer,
+                            &room_sink,
+                            &mut player_id,
+                            &msg,
+                            legacy_protocol,
+                            &mut rate_limiter,
+                        )
+                        .await;
+                        if let Err(err) = ws_sender.send(Message::Text(resp_msg)).await {
+                            eprintln!("send response msg to client {} failed: {}", player_id, err);
+                            break;
                         }
-                        _ => {
-                            eprintln!("unsupported message type");
-                        }
-                    },
-                    Err(err) => {
-                        match err {
-                            websocket::WebSocketError::IoError(v) => {
-                                match v.kind() {
-                                    // err will be IoError when non_blocking
-                                    std::io::ErrorKind::WouldBlock => {}
-                                    _ => {
-                                        eprintln!("recv msg failed, io error: {}", v);
-                                        client_failure_or_closed = true;
-                                    }
-                                }
-                            }
-                            websocket::WebSocketError::NoDataAvailable => {}
-                            _ => {
-                                eprintln!("recv msg failed: {}", err);
-                                client_failure_or_closed = true;
-                            }
+                    }
+                    Some(Ok(Message::Close(_))) | None => {
+                        eprintln!("client closing connection");
+                        break;
+                    }
+                    // pings are answered by tungstenite
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => {
+                        eprintln!("recv msg of client {} failed: {}", &peer_ip_addr, err);
+                        break;
+                    }
+                },
+                // msg from other tasks
+                data = receiver.recv() => match data {
+                    Some(data) => {
+                        if let Err(err) = ws_sender.send(Message::Text(data)).await {
+                            eprintln!("send msg to client {} failed: {}", player_id, err);
+                            break;
                         }
                     }
+                    None => {
+                        eprintln!("sender of {} become disconnected", &player_id);
+                        break;
+                    }
+                },
+                // the sink is held here, so the channel never closes
+                Some((room_id, room)) = room_receiver.recv() => {
+                    rooms.insert(room_id, room);
                 }
-                if client_failure_or_closed {
-                    break;
+                // so is the closer
+                Some(()) = closed.recv() => {
+                    eprintln!("client {} resumed on another connection", player_id);
+                    let frame = CloseFrame {
+                        code: CloseCode::Policy,
+                        reason: "resumed on another connection".into(),
+                    };
+                    ws_sender.send(Message::Close(Some(frame))).await.unwrap_or(());
+                    // the player, its rooms and its place in the queue belong to the other
+                    // connection now
+                    return;
                 }
+            }
+        }
+    }
 
-                // check any msg from other threads
-                match receiver.try_recv() {
-                    Ok(v) => job_queue.push_back(v),
-                    Err(err) => match err {
-                        mpsc::TryRecvError::Empty => {}
-                        mpsc::TryRecvError::Disconnected => {
-                            eprintln!("sender of {} become disconnected", &player_id);
-                            client_failure_or_closed = true;
-                        }
-                    },
-                }
-                if client_failure_or_closed {
-                    break;
-                }
+    // shutdown
+    ws_sender.close().await.unwrap_or(());
+    // no room is sent to the sink once the player is out of the queue
+    lobby.leave_queue(player_id).await.ok();
+    while let Ok((room_id, room)) = room_receiver.try_recv() {
+        rooms.insert(room_id, room);
+    }
+    rooms
+        .values()
+        .for_each(|room| room.leave(player_id, sender.clone()));
+    lobby.disconnect(player_id, sender);
+}
 
-                // handle those msg
-                while !job_queue.is_empty() {
-                    let tmp_job = job_queue.front().unwrap();
-                    match tmp_job.job_cmd {
-                        ThreadJobCmd::SendData => {
-                            match ws_client.send_message(&Message::text(&tmp_job.data)) {
-                                Ok(_) => {
-                                    job_queue.pop_front();
-                                }
-                                Err(err) => match err {
-                                    websocket::WebSocketError::IoError(v) => match v.kind() {
-                                        std::io::ErrorKind::WouldBlock => {}
-                                        _ => {
-                                            eprintln!(
-                                                "send msg to client {} failed, io error: {}",
-                                                player_id, v
-                                            );
-                                            client_failure_or_closed = true;
-                                        }
-                                    },
-                                    _ => {
-                                        eprintln!(
-                                            "send msg to client {} failed: {}",
-                                            player_id, err
-                                        );
-                                        client_failure_or_closed = true;
-                                    }
-                                },
-                            }
-                        }
-                    }
-                }
-                if client_failure_or_closed {
-                    break;
-                }
+#[tokio::main]
+async fn main() {
+    let port = 8686;
+    let args = std::env::args().collect::<Vec<String>>();
+    // the React client still talks in the newline separated format
+    let legacy_protocol = args.iter().any(|v| v == "--legacy-protocol");
+    // 0 gives up the seat as soon as the connection drops
+    let grace_secs = match args.iter().position(|v| v == "--grace-secs") {
+        Some(i) => args
+            .get(i + 1)
+            .and_then(|v| v.parse().ok())
+            .expect("--grace-secs takes a number of seconds"),
+        None => DEFAULT_GRACE_SECS,
+    };
+    let db_path = match args.iter().position(|v| v == "--db") {
+        Some(i) => args.get(i + 1).expect("--db takes a file path").as_str(),
+        None => DEFAULT_DB_PATH,
+    };
+    // 0 starts one thread per cpu
+    let bot_threads = match args.iter().position(|v| v == "--bot-threads") {
+        Some(i) => args
+            .get(i + 1)
+            .and_then(|v| v.parse().ok())
+            .expect("--bot-threads takes a number of threads"),
+        None => 0,
+    };
+    let open = || {
+        Store::open(db_path)
+            .unwrap_or_else(|err| panic!("open database {} failed: {}", db_path, err))
+    };
+    let store = open();
+    let reports = open();
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))
+        .await
+        .unwrap_or_else(|_| panic!("bind websocket to port {} failed", port));
+
+    let bots = BotPool::new(bot_threads);
+    let lobby = LobbyHandle::spawn(store, reports, Duration::from_secs(grace_secs), bots);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer_addr)) => {
+                tokio::spawn(serve_client(
+                    stream,
+                    peer_addr,
+                    lobby.clone(),
+                    legacy_protocol,
+                ));
             }
-            // thread::sleep(Duration::from_millis(10));
-            shut_down(&ws_client, &cloned_channels_map);
-        });
+            Err(err) => eprintln!("accept failed: {}", err),
+        }
     }
 }
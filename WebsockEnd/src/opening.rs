@@ -0,0 +1,216 @@
+//! Opening protocols balancing the advantage of moving first.
+//!
+//! PLAYER1 starts as black and PLAYER2 as white, the opening decides whether they swap.
+//! In every protocol PLAYER1 first places three stones (black, white, black) and then:
+//! - swap: PLAYER2 chooses a color
+//! - swap2: PLAYER2 chooses a color, or places two more stones (white, black) and lets
+//!   PLAYER1 choose
+//! - soosorv8: PLAYER2 chooses a color, white places the 4th stone and says how many 5th
+//!   moves black has to propose (1 to 8), black may swap again, black proposes the 5th
+//!   moves and white picks the one to be played
+use serde::{Deserialize, Serialize};
+
+use crate::gomoku_ol::{MoveError, PieceType};
+
+/// most 5th moves black can be asked to propose in soosorv8
+pub const MAX_FIFTH_COUNT: usize = 8;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Opening {
+    /// no opening, black simply moves first
+    #[default]
+    Free,
+    Swap,
+    Swap2,
+    Soosorv8,
+}
+impl Opening {
+    pub fn first_phase(self) -> OpeningPhase {
+        match self {
+            Opening::Free => OpeningPhase::Done,
+            _ => OpeningPhase::PlaceThree,
+        }
+    }
+}
+
+/// who has to act in a phase, a seat or whoever holds a color
+#[derive(Clone, Copy)]
+pub enum Actor {
+    Player1,
+    Player2,
+    Color(PieceType),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum OpeningPhase {
+    /// PLAYER1 places the first three stones
+    PlaceThree,
+    /// PLAYER2 chooses a color, in swap2 it may place two more stones instead
+    FirstChoice,
+    /// PLAYER1 chooses a color after the two extra stones of swap2
+    SecondChoice,
+    /// white places the 4th stone and sets the number of 5th moves to propose
+    PlaceFourth,
+    /// black may swap after the 4th stone
+    FourthChoice { fifth_count: usize },
+    /// black proposes fifth_count 5th moves
+    ProposeFifth { fifth_count: usize },
+    /// white picks one of the proposals as the 5th move
+    ChooseFifth { proposals: Vec<(usize, usize)> },
+    /// the opening is over, or there was none
+    Done,
+}
+impl OpeningPhase {
+    pub fn actor(&self) -> Option<Actor> {
+        match self {
+            OpeningPhase::PlaceThree | OpeningPhase::SecondChoice => Some(Actor::Player1),
+            OpeningPhase::FirstChoice => Some(Actor::Player2),
+            OpeningPhase::PlaceFourth | OpeningPhase::ChooseFifth { .. } => {
+                Some(Actor::Color(PieceType::WHITE))
+            }
+            OpeningPhase::FourthChoice { .. } | OpeningPhase::ProposeFifth { .. } => {
+                Some(Actor::Color(PieceType::BLACK))
+            }
+            OpeningPhase::Done => None,
+        }
+    }
+
+    /// the phase after the actor placed stones with `place_opening`
+    pub fn after_placing(
+        &self,
+        opening: Opening,
+        stones: usize,
+        fifth_count: Option<usize>,
+    ) -> Result<OpeningPhase, MoveError> {
+        match (self, opening) {
+            (OpeningPhase::PlaceThree, _) if stones == 3 && fifth_count.is_none() => {
+                Ok(OpeningPhase::FirstChoice)
+            }
+            (OpeningPhase::FirstChoice, Opening::Swap2) if stones == 2 && fifth_count.is_none() => {
+                Ok(OpeningPhase::SecondChoice)
+            }
+            (OpeningPhase::PlaceFourth, _) if stones == 1 => match fifth_count {
+                Some(fifth_count) if (1..=MAX_FIFTH_COUNT).contains(&fifth_count) => {
+                    Ok(OpeningPhase::FourthChoice { fifth_count })
+                }
+                _ => Err(MoveError::BadFifthCount),
+            },
+            (OpeningPhase::PlaceThree, _)
+            | (OpeningPhase::FirstChoice, Opening::Swap2)
+            | (OpeningPhase::PlaceFourth, _) => Err(MoveError::WrongStoneCount),
+            _ => Err(MoveError::WrongPhase),
+        }
+    }
+
+    /// the phase after the actor chose a color
+    pub fn after_choosing(&self, opening: Opening) -> Result<OpeningPhase, MoveError> {
+        match (self, opening) {
+            (OpeningPhase::FirstChoice, Opening::Soosorv8) => Ok(OpeningPhase::PlaceFourth),
+            (OpeningPhase::FirstChoice, _) | (OpeningPhase::SecondChoice, _) => {
+                Ok(OpeningPhase::Done)
+            }
+            (OpeningPhase::FourthChoice { fifth_count }, _) => Ok(OpeningPhase::ProposeFifth {
+                fifth_count: *fifth_count,
+            }),
+            _ => Err(MoveError::WrongPhase),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_has_no_opening() {
+        assert_eq!(Opening::Free.first_phase(), OpeningPhase::Done);
+        assert!(OpeningPhase::Done.actor().is_none());
+    }
+
+    #[test]
+    fn swap_ends_with_the_first_choice() {
+        let phase = Opening::Swap.first_phase();
+        let phase = phase.after_placing(Opening::Swap, 3, None).unwrap();
+        assert_eq!(phase, OpeningPhase::FirstChoice);
+        assert_eq!(
+            phase.after_placing(Opening::Swap, 2, None),
+            Err(MoveError::WrongPhase)
+        );
+        assert_eq!(phase.after_choosing(Opening::Swap), Ok(OpeningPhase::Done));
+    }
+
+    #[test]
+    fn swap2_may_place_two_more_stones() {
+        let phase = OpeningPhase::FirstChoice;
+        assert_eq!(phase.after_choosing(Opening::Swap2), Ok(OpeningPhase::Done));
+        let phase = phase.after_placing(Opening::Swap2, 2, None).unwrap();
+        assert_eq!(phase, OpeningPhase::SecondChoice);
+        assert!(matches!(phase.actor(), Some(Actor::Player1)));
+        assert_eq!(phase.after_choosing(Opening::Swap2), Ok(OpeningPhase::Done));
+    }
+
+    #[test]
+    fn soosorv8_goes_through_every_phase() {
+        let phase = OpeningPhase::FirstChoice
+            .after_choosing(Opening::Soosorv8)
+            .unwrap();
+        assert_eq!(phase, OpeningPhase::PlaceFourth);
+        assert!(matches!(
+            phase.actor(),
+            Some(Actor::Color(PieceType::WHITE))
+        ));
+        let phase = phase.after_placing(Opening::Soosorv8, 1, Some(3)).unwrap();
+        assert_eq!(phase, OpeningPhase::FourthChoice { fifth_count: 3 });
+        assert!(matches!(
+            phase.actor(),
+            Some(Actor::Color(PieceType::BLACK))
+        ));
+        let phase = phase.after_choosing(Opening::Soosorv8).unwrap();
+        assert_eq!(phase, OpeningPhase::ProposeFifth { fifth_count: 3 });
+        assert_eq!(
+            phase.after_choosing(Opening::Soosorv8),
+            Err(MoveError::WrongPhase)
+        );
+    }
+
+    #[test]
+    fn placing_rejects_the_wrong_number_of_stones() {
+        for opening in [Opening::Swap, Opening::Swap2, Opening::Soosorv8] {
+            assert_eq!(
+                OpeningPhase::PlaceThree.after_placing(opening, 2, None),
+                Err(MoveError::WrongStoneCount)
+            );
+            assert_eq!(
+                OpeningPhase::PlaceThree.after_placing(opening, 3, Some(1)),
+                Err(MoveError::WrongStoneCount)
+            );
+        }
+        assert_eq!(
+            OpeningPhase::FirstChoice.after_placing(Opening::Swap2, 3, None),
+            Err(MoveError::WrongStoneCount)
+        );
+        assert_eq!(
+            OpeningPhase::PlaceFourth.after_placing(Opening::Soosorv8, 2, Some(1)),
+            Err(MoveError::WrongStoneCount)
+        );
+    }
+
+    #[test]
+    fn placing_the_fourth_stone_needs_a_fifth_count_in_range() {
+        let phase = OpeningPhase::PlaceFourth;
+        for fifth_count in [None, Some(0), Some(MAX_FIFTH_COUNT + 1)] {
+            assert_eq!(
+                phase.after_placing(Opening::Soosorv8, 1, fifth_count),
+                Err(MoveError::BadFifthCount)
+            );
+        }
+        assert_eq!(
+            phase.after_placing(Opening::Soosorv8, 1, Some(MAX_FIFTH_COUNT)),
+            Ok(OpeningPhase::FourthChoice {
+                fifth_count: MAX_FIFTH_COUNT
+            })
+        );
+    }
+}
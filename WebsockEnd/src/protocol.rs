@@ -0,0 +1,814 @@
+//! Messages between the server and clients.
+//!
+//! A client sends json requests like `{"type":"put_piece","room_id":1,"row_i":3,"col_j":4}`,
+//! optionally with a `req_id` that is echoed back, the server answers each of them with a
+//! [`Response`] and pushes [`Event`]s to the players concerned. Events of a room carry a `seq`
+//! increasing by one per event of that room (lobby events have their own), so a gap means
+//! something was missed and `sync_room` should be sent. The legacy newline separated format
+//! of the React client is still understood by [`Request::from_legacy`].
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::bot::Engine;
+use crate::clock::TimeControl;
+use crate::error::{Error, ErrorCode};
+use crate::gomoku_ol::{
+    EndReason, GameStatus, PieceType, Stats, DEFAULT_BOARD_SIZE, DEFAULT_WIN_LENGTH,
+};
+use crate::opening::{Opening, OpeningPhase};
+use crate::rules::RuleSet;
+
+/// request of a client
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    CreateRoom {
+        name: String,
+        /// fields left out take their default
+        #[serde(flatten)]
+        settings: GameSettings,
+    },
+    RoomList,
+    EnterRoom {
+        room_id: i32,
+    },
+    ExitRoom {
+        room_id: i32,
+    },
+    ResetGame {
+        room_id: i32,
+    },
+    SyncRoom {
+        room_id: i32,
+    },
+    PutPiece {
+        room_id: i32,
+        row_i: usize,
+        col_j: usize,
+    },
+    /// ask the opponent to take back the last move
+    UnputPiece {
+        room_id: i32,
+    },
+    AnswerUnput {
+        room_id: i32,
+        accept: bool,
+    },
+    /// points the side to move can not play on, for highlighting
+    ForbiddenPoints {
+        room_id: i32,
+    },
+    /// stones of the current phase of the opening, fifth_count only with the 4th stone of soosorv8
+    PlaceOpening {
+        room_id: i32,
+        stones: Vec<(usize, usize)>,
+        #[serde(default)]
+        fifth_count: Option<usize>,
+    },
+    ChooseColor {
+        room_id: i32,
+        piece_type: PieceType,
+    },
+    ProposeFifth {
+        room_id: i32,
+        points: Vec<(usize, usize)>,
+    },
+    ChooseFifth {
+        room_id: i32,
+        row_i: usize,
+        col_j: usize,
+    },
+    Resign {
+        room_id: i32,
+    },
+    /// offering when the opponent has offered already agrees to the draw
+    OfferDraw {
+        room_id: i32,
+    },
+    AcceptDraw {
+        room_id: i32,
+    },
+    /// take over the player the token of `your_id` was issued to, after the connection
+    /// dropped. Its rooms are rejoined and events missed meanwhile are sent again
+    Resume {
+        token: String,
+    },
+    /// nicknames are unique among players online
+    SetNickname {
+        nickname: String,
+    },
+    Profile {
+        player_id: i32,
+    },
+    /// create an account and log in to it, the username becomes the nickname
+    Register {
+        username: String,
+        password: String,
+    },
+    /// stats of the account are kept from game to game and across restarts
+    Login {
+        username: String,
+        password: String,
+    },
+    /// accounts with rated games by rating, highest first
+    Leaderboard {
+        #[serde(default)]
+        offset: u32,
+        /// DEFAULT_LEADERBOARD_LIMIT if missing
+        #[serde(default)]
+        limit: Option<u32>,
+    },
+    /// statistics of an account from its archived games
+    PlayerStats {
+        username: String,
+    },
+    /// wait for an opponent with a close rating asking for the same settings, both are
+    /// seated in a new room and get `match_found`
+    JoinQueue {
+        /// fields left out take their default
+        #[serde(flatten)]
+        settings: GameSettings,
+    },
+    LeaveQueue,
+    /// seat a bot in the free seat of a room without an opening, level goes from
+    /// bot::MIN_LEVEL to bot::MAX_LEVEL
+    AddBot {
+        room_id: i32,
+        #[serde(default)]
+        engine: Engine,
+        /// bot::DEFAULT_LEVEL if missing
+        #[serde(default)]
+        level: Option<u8>,
+    },
+}
+impl Request {
+    /// value of `type` of every request
+    pub const COMMANDS: [&'static str; 27] = [
+        "create_room",
+        "room_list",
+        "enter_room",
+        "exit_room",
+        "reset_game",
+        "sync_room",
+        "put_piece",
+        "unput_piece",
+        "answer_unput",
+        "forbidden_points",
+        "place_opening",
+        "choose_color",
+        "propose_fifth",
+        "choose_fifth",
+        "resign",
+        "offer_draw",
+        "accept_draw",
+        "resume",
+        "set_nickname",
+        "profile",
+        "register",
+        "login",
+        "leaderboard",
+        "player_stats",
+        "join_queue",
+        "leave_queue",
+        "add_bot",
+    ];
+
+    /// room the request is about, None for lobby requests
+    pub fn room_id(&self) -> Option<i32> {
+        match self {
+            Request::CreateRoom { .. }
+            | Request::RoomList
+            | Request::Resume { .. }
+            | Request::SetNickname { .. }
+            | Request::Profile { .. }
+            | Request::Register { .. }
+            | Request::Login { .. }
+            | Request::Leaderboard { .. }
+            | Request::PlayerStats { .. }
+            | Request::JoinQueue { .. }
+            | Request::LeaveQueue => None,
+            Request::EnterRoom { room_id }
+            | Request::ExitRoom { room_id }
+            | Request::ResetGame { room_id }
+            | Request::SyncRoom { room_id }
+            | Request::PutPiece { room_id, .. }
+            | Request::UnputPiece { room_id }
+            | Request::AnswerUnput { room_id, .. }
+            | Request::ForbiddenPoints { room_id }
+            | Request::PlaceOpening { room_id, .. }
+            | Request::ChooseColor { room_id, .. }
+            | Request::ProposeFifth { room_id, .. }
+            | Request::ChooseFifth { room_id, .. }
+            | Request::Resign { room_id }
+            | Request::OfferDraw { room_id }
+            | Request::AcceptDraw { room_id }
+            | Request::AddBot { room_id, .. } => Some(*room_id),
+        }
+    }
+
+    /// parse a request of type resp_for from v
+    fn from_value(resp_for: &str, v: Value) -> Result<Request, Error> {
+        if resp_for.is_empty() {
+            return Err(Error::bad_request("missing type of request"));
+        }
+        if !Request::COMMANDS.contains(&resp_for) {
+            return Err(Error::new(
+                ErrorCode::UnknownCommand,
+                &format!("unknown command {}", resp_for),
+            ));
+        }
+        serde_json::from_value(v).map_err(|err| Error::bad_request(&err.to_string()))
+    }
+
+    /// parse the newline separated format, e.g. `put_piece\n1\n3\n4`, of the connection
+    /// player_id. Legacy clients send their own id before room_id in `enter_room`/`exit_room`
+    /// and a piece type after `put_piece`, the id has to be the connection's and the piece
+    /// type is ignored
+    pub fn from_legacy(msg: &str, player_id: i32) -> Option<Request> {
+        let lines: Vec<&str> = msg.split('\n').collect();
+        let is_own_id = |v: &str| v.parse::<i32>() == Ok(player_id);
+        match (lines[0], lines.len()) {
+            ("create_room", 2) => Some(Request::CreateRoom {
+                name: lines[1].to_string(),
+                settings: GameSettings::default(),
+            }),
+            ("create_room", 5) => Some(Request::CreateRoom {
+                name: lines[1].to_string(),
+                settings: GameSettings {
+                    row_size: lines[2].parse().ok()?,
+                    col_size: lines[3].parse().ok()?,
+                    win_length: lines[4].parse().ok()?,
+                    rule: RuleSet::default(),
+                    opening: Opening::default(),
+                    time_control: None,
+                    rated: false,
+                },
+            }),
+            ("create_room", 6) => Some(Request::CreateRoom {
+                name: lines[1].to_string(),
+                settings: GameSettings {
+                    row_size: lines[2].parse().ok()?,
+                    col_size: lines[3].parse().ok()?,
+                    win_length: lines[4].parse().ok()?,
+                    rule: serde_json::from_value(Value::from(lines[5])).ok()?,
+                    opening: Opening::default(),
+                    time_control: None,
+                    rated: false,
+                },
+            }),
+            ("room_list", _) => Some(Request::RoomList),
+            ("enter_room", 2) => Some(Request::EnterRoom {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("enter_room", 3) if is_own_id(lines[1]) => Some(Request::EnterRoom {
+                room_id: lines[2].parse().ok()?,
+            }),
+            ("exit_room", 2) => Some(Request::ExitRoom {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("exit_room", 3) if is_own_id(lines[1]) => Some(Request::ExitRoom {
+                room_id: lines[2].parse().ok()?,
+            }),
+            ("reset_game", 2) => Some(Request::ResetGame {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("sync_room", 2) => Some(Request::SyncRoom {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("put_piece", 4) | ("put_piece", 5) => Some(Request::PutPiece {
+                room_id: lines[1].parse().ok()?,
+                row_i: lines[2].parse().ok()?,
+                col_j: lines[3].parse().ok()?,
+            }),
+            ("unput_piece", 2) => Some(Request::UnputPiece {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("answer_unput", 3) => Some(Request::AnswerUnput {
+                room_id: lines[1].parse().ok()?,
+                accept: lines[2] == "1",
+            }),
+            ("forbidden_points", 2) => Some(Request::ForbiddenPoints {
+                room_id: lines[1].parse().ok()?,
+            }),
+            // points are given as row_i and col_j lines one after another, an odd line
+            // left at the end is fifth_count
+            ("place_opening", n) if n >= 3 => Some(Request::PlaceOpening {
+                room_id: lines[1].parse().ok()?,
+                stones: legacy_points(&lines[2..n - (n - 2) % 2])?,
+                fifth_count: match (n - 2) % 2 {
+                    1 => Some(lines[n - 1].parse().ok()?),
+                    _ => None,
+                },
+            }),
+            ("choose_color", 3) => Some(Request::ChooseColor {
+                room_id: lines[1].parse().ok()?,
+                piece_type: PieceType::from_i32(lines[2].parse().ok()?),
+            }),
+            ("propose_fifth", n) if n >= 4 => Some(Request::ProposeFifth {
+                room_id: lines[1].parse().ok()?,
+                points: legacy_points(&lines[2..])?,
+            }),
+            ("choose_fifth", 4) => Some(Request::ChooseFifth {
+                room_id: lines[1].parse().ok()?,
+                row_i: lines[2].parse().ok()?,
+                col_j: lines[3].parse().ok()?,
+            }),
+            ("resign", 2) => Some(Request::Resign {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("offer_draw", 2) => Some(Request::OfferDraw {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("accept_draw", 2) => Some(Request::AcceptDraw {
+                room_id: lines[1].parse().ok()?,
+            }),
+            ("resume", 2) => Some(Request::Resume {
+                token: lines[1].to_string(),
+            }),
+            ("set_nickname", 2) => Some(Request::SetNickname {
+                nickname: lines[1].to_string(),
+            }),
+            ("profile", 2) => Some(Request::Profile {
+                player_id: lines[1].parse().ok()?,
+            }),
+            ("register", 3) => Some(Request::Register {
+                username: lines[1].to_string(),
+                password: lines[2].to_string(),
+            }),
+            ("login", 3) => Some(Request::Login {
+                username: lines[1].to_string(),
+                password: lines[2].to_string(),
+            }),
+            ("leaderboard", 1) => Some(Request::Leaderboard {
+                offset: 0,
+                limit: None,
+            }),
+            ("leaderboard", 3) => Some(Request::Leaderboard {
+                offset: lines[1].parse().ok()?,
+                limit: Some(lines[2].parse().ok()?),
+            }),
+            ("player_stats", 2) => Some(Request::PlayerStats {
+                username: lines[1].to_string(),
+            }),
+            ("join_queue", 1) => Some(Request::JoinQueue {
+                settings: GameSettings::default(),
+            }),
+            ("leave_queue", 1) => Some(Request::LeaveQueue),
+            ("add_bot", 2) => Some(Request::AddBot {
+                room_id: lines[1].parse().ok()?,
+                engine: Engine::default(),
+                level: None,
+            }),
+            ("add_bot", 3) => Some(Request::AddBot {
+                room_id: lines[1].parse().ok()?,
+                engine: Engine::default(),
+                level: Some(lines[2].parse().ok()?),
+            }),
+            ("add_bot", 4) => Some(Request::AddBot {
+                room_id: lines[1].parse().ok()?,
+                level: Some(lines[2].parse().ok()?),
+                engine: serde_json::from_value(Value::from(lines[3])).ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// points from lines of row_i and col_j one after another
+fn legacy_points(lines: &[&str]) -> Option<Vec<(usize, usize)>> {
+    if !lines.len().is_multiple_of(2) {
+        return None;
+    }
+    lines
+        .chunks(2)
+        .map(|v| Some((v[0].parse().ok()?, v[1].parse().ok()?)))
+        .collect()
+}
+
+/// a message of client, with what is needed to answer it even if it can not be understood
+pub struct Incoming {
+    /// type of the request
+    pub resp_for: String,
+    pub req_id: Option<Value>,
+    pub request: Result<Request, Error>,
+}
+impl Incoming {
+    /// parse msg of the connection player_id, messages not starting with `{` are taken as the
+    /// legacy format if legacy_protocol is enabled
+    pub fn parse(msg: &str, player_id: i32, legacy_protocol: bool) -> Incoming {
+        if legacy_protocol && !msg.trim_start().starts_with('{') {
+            let resp_for = msg.split('\n').next().unwrap_or_default().to_string();
+            let request = if Request::COMMANDS.contains(&resp_for.as_str()) {
+                Request::from_legacy(msg, player_id)
+                    .ok_or_else(|| Error::bad_request("invalid arguments"))
+            } else {
+                Err(Error::new(
+                    ErrorCode::UnknownCommand,
+                    &format!("unknown command {}", resp_for),
+                ))
+            };
+            return Incoming {
+                resp_for,
+                req_id: None,
+                request,
+            };
+        }
+        match serde_json::from_str::<Value>(msg) {
+            Ok(mut v) => {
+                let resp_for = v
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                Incoming {
+                    req_id: v.as_object_mut().and_then(|v| v.remove("req_id")),
+                    request: Request::from_value(&resp_for, v),
+                    resp_for,
+                }
+            }
+            Err(err) => Incoming {
+                resp_for: String::new(),
+                req_id: None,
+                request: Err(Error::bad_request(&err.to_string())),
+            },
+        }
+    }
+}
+
+/// answer to a request, `resp_for` is the type of the request and `req_id` is the one it carried
+#[derive(Serialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(rename = "type")]
+    pub resp_for: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub req_id: Option<Value>,
+    pub data: Value,
+}
+impl Response {
+    pub fn ok(resp_for: &str, data: Value) -> Response {
+        Response {
+            ok: true,
+            resp_for: resp_for.to_string(),
+            req_id: None,
+            data,
+        }
+    }
+    /// the request failed with err
+    pub fn err(resp_for: &str, err: &Error) -> Response {
+        Response {
+            ok: false,
+            resp_for: resp_for.to_string(),
+            req_id: None,
+            data: serde_json::to_value(err).unwrap(),
+        }
+    }
+    pub fn with_req_id(mut self, req_id: Option<Value>) -> Response {
+        self.req_id = req_id;
+        self
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// message pushed to players without them asking
+#[derive(Serialize)]
+#[serde(tag = "msg_others", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    RoomList(Vec<ListedRoom>),
+    /// the queue seated the player in a new room, sent to both players only
+    MatchFound {
+        room_id: i32,
+        room: Box<RoomState>,
+    },
+    EnterRoom {
+        room_id: i32,
+        player_id: i32,
+        player_type: i32,
+        nickname: String,
+    },
+    ExitRoom {
+        room_id: i32,
+        player_id: i32,
+        player_type: i32,
+        nickname: String,
+    },
+    /// a member changed its nickname
+    SetNickname {
+        room_id: i32,
+        player_id: i32,
+        nickname: String,
+    },
+    Reset {
+        room_id: i32,
+    },
+    PutPiece {
+        room_id: i32,
+        row_i: usize,
+        col_j: usize,
+        piece_type: PieceType,
+        /// the clocks right after the move, in rooms with a time control
+        #[serde(skip_serializing_if = "Option::is_none")]
+        clocks: Option<ClocksView>,
+    },
+    GameOver(GameOver),
+    /// a seated player lost connection, its seat is held for grace_ms
+    OpponentDisconnected {
+        room_id: i32,
+        player_id: i32,
+        grace_ms: u64,
+    },
+    OpponentReconnected {
+        room_id: i32,
+        player_id: i32,
+    },
+    DrawOffer {
+        room_id: i32,
+        player_id: i32,
+    },
+    /// a player asked to reset the game in progress, the opponent resets it too to agree
+    ResetRequest {
+        room_id: i32,
+        player_id: i32,
+    },
+    UnputRequest {
+        room_id: i32,
+        player_id: i32,
+    },
+    UnputDeclined {
+        room_id: i32,
+        player_id: i32,
+    },
+    /// pieces taken back, latest first
+    UnputPiece {
+        room_id: i32,
+        pieces: Vec<(usize, usize)>,
+    },
+    /// opening events come with the phase of the opening that follows
+    PlaceOpening {
+        room_id: i32,
+        player_id: i32,
+        /// (row_i, col_j, piece_type) in order
+        stones: Vec<(usize, usize, PieceType)>,
+        opening: OpeningView,
+    },
+    ChooseColor {
+        room_id: i32,
+        player_id: i32,
+        piece_type: PieceType,
+        opening: OpeningView,
+    },
+    ProposeFifth {
+        room_id: i32,
+        player_id: i32,
+        points: Vec<(usize, usize)>,
+        opening: OpeningView,
+    },
+    ChooseFifth {
+        room_id: i32,
+        player_id: i32,
+        row_i: usize,
+        col_j: usize,
+        opening: OpeningView,
+    },
+}
+impl Event {
+    /// json of the event numbered seq
+    pub fn to_json(&self, seq: u64) -> String {
+        #[derive(Serialize)]
+        struct Numbered<'a> {
+            #[serde(flatten)]
+            event: &'a Event,
+            seq: u64,
+        }
+        serde_json::to_string(&Numbered { event: self, seq }).unwrap()
+    }
+}
+
+#[derive(Serialize)]
+pub struct RoomBrief {
+    pub id: i32,
+    pub name: String,
+}
+
+/// a room with its game shown as G
+#[derive(Clone, Serialize)]
+pub struct RoomView<G> {
+    pub id: i32,
+    pub name: String,
+    /// player id to player type
+    pub game_players: BTreeMap<i32, i32>,
+    pub game_observers: Vec<i32>,
+    /// players whose seat is held while they are away
+    pub disconnected: Vec<i32>,
+    /// player id to nickname, for every member
+    pub nicknames: BTreeMap<i32, String>,
+    /// seq of the latest event of the room
+    pub seq: u64,
+    pub game: G,
+}
+/// room as rooms tell the lobby about it
+pub type RoomInfo = RoomView<GameSettings>;
+/// room in the room list
+#[derive(Serialize)]
+pub struct ListedRoom {
+    #[serde(flatten)]
+    pub info: RoomInfo,
+    /// player id to rating, for seated players with an account
+    pub ratings: BTreeMap<i32, RatingView>,
+}
+/// room with everything needed to render it
+pub type RoomState = RoomView<GameState>;
+
+/// settings of a game, chosen at `create_room`
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct GameSettings {
+    pub row_size: usize,
+    pub col_size: usize,
+    /// number of pieces in a row needed to win
+    pub win_length: usize,
+    pub rule: RuleSet,
+    pub opening: Opening,
+    /// None for untimed games
+    pub time_control: Option<TimeControl>,
+    /// results change the ratings of the players, if both are logged in
+    pub rated: bool,
+}
+impl Default for GameSettings {
+    fn default() -> GameSettings {
+        GameSettings {
+            row_size: DEFAULT_BOARD_SIZE,
+            col_size: DEFAULT_BOARD_SIZE,
+            win_length: DEFAULT_WIN_LENGTH,
+            rule: RuleSet::default(),
+            opening: Opening::default(),
+            time_control: None,
+            rated: false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GameState {
+    #[serde(flatten)]
+    pub settings: GameSettings,
+    pub board: Vec<Vec<PieceType>>,
+    /// (row_i, col_j, piece_type) in order
+    pub moves: Vec<(usize, usize, PieceType)>,
+    pub turn: PieceType,
+    /// EMPTY when there is no pending takeback
+    pub undo_requester: PieceType,
+    /// EMPTY when there is no pending draw offer
+    pub draw_offerer: PieceType,
+    /// EMPTY when nobody asked to reset the game in progress
+    pub reset_requester: PieceType,
+    pub status: GameStatus,
+    /// why the game ended, once it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<EndReason>,
+    /// the winning line of a game won by five
+    pub line: Vec<(usize, usize)>,
+    /// points the side to move can not play on
+    pub forbidden: Vec<(usize, usize)>,
+    pub opening: OpeningView,
+    /// None for untimed games
+    pub clocks: Option<ClocksView>,
+}
+
+#[derive(Serialize)]
+pub struct OpeningView {
+    #[serde(flatten)]
+    pub phase: OpeningPhase,
+    /// player type of the seat that has to act, -1 once the opening is done
+    pub player_type: i32,
+    /// PLAYER1 holds white and PLAYER2 black
+    pub swapped: bool,
+}
+
+#[derive(Serialize)]
+pub struct GameOver {
+    pub room_id: i32,
+    pub status: GameStatus,
+    pub reason: EndReason,
+    /// EMPTY for draws and aborted games
+    pub winner: PieceType,
+    pub line: Vec<(usize, usize)>,
+}
+
+#[derive(Serialize)]
+pub struct ClocksView {
+    pub black: ClockView,
+    pub white: ClockView,
+    /// side whose clock is running, EMPTY before the first move after the opening and
+    /// once the game is over
+    pub running: PieceType,
+}
+
+#[derive(Serialize)]
+pub struct ClockView {
+    /// milliseconds left of main time, or of the current period in byo-yomi
+    pub remaining_ms: u64,
+    /// main time is used up
+    pub byoyomi: bool,
+    /// byo-yomi periods left
+    pub periods: u32,
+}
+
+/// what everyone can see of a player
+#[derive(Serialize)]
+pub struct Profile {
+    pub player_id: i32,
+    pub nickname: String,
+    /// logged in to an account, stats of guests are lost when they leave
+    pub registered: bool,
+    /// None for guests
+    pub rating: Option<RatingView>,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+/// Glicko-2 rating as clients see it
+#[derive(Clone, Copy, Serialize)]
+pub struct RatingView {
+    pub rating: i32,
+    /// rating deviation
+    pub deviation: i32,
+}
+
+/// a page of the leaderboard
+#[derive(Serialize)]
+pub struct Leaderboard {
+    /// number of accounts on the whole leaderboard
+    pub total: u32,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+#[derive(Serialize)]
+pub struct LeaderboardEntry {
+    /// 1 for the highest rating
+    pub rank: u32,
+    pub username: String,
+    #[serde(flatten)]
+    pub rating: RatingView,
+    /// number of rated games
+    pub rated_games: u32,
+}
+
+/// statistics of an account over its archived games, aborted games are not archived
+#[derive(Serialize)]
+pub struct PlayerStats {
+    pub username: String,
+    pub rating: RatingView,
+    pub games: u32,
+    pub rated_games: u32,
+    pub wins_as_black: u32,
+    pub wins_as_white: u32,
+    pub losses: u32,
+    pub draws: u32,
+    /// average number of moves per game
+    pub average_moves: f64,
+    /// wins in a row up to the latest game if positive, losses if negative
+    pub current_streak: i32,
+    pub longest_win_streak: u32,
+    pub longest_loss_streak: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the `type` of every variant of Request, as serde lists them when rejecting an unknown one
+    fn variant_names() -> Vec<String> {
+        let err = serde_json::from_str::<Request>(r#"{"type": "no_such_command"}"#)
+            .err()
+            .unwrap()
+            .to_string();
+        let expected = err.split("expected one of ").nth(1).unwrap();
+        expected
+            .split(", ")
+            .map(|v| v.split('`').nth(1).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn commands_are_the_variants_of_request() {
+        let mut names = variant_names();
+        names.sort_unstable();
+        let mut commands = Request::COMMANDS.to_vec();
+        commands.sort_unstable();
+        assert_eq!(names, commands);
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        let incoming = Incoming::parse(r#"{"type": "fly"}"#, 1, false);
+        assert_eq!(incoming.resp_for, "fly");
+        let err = incoming.request.err().unwrap();
+        assert_eq!(err.code, ErrorCode::UnknownCommand);
+        let incoming = Incoming::parse(r#"{"type": "put_piece", "room_id": 1}"#, 1, false);
+        assert_eq!(incoming.request.err().unwrap().code, ErrorCode::BadRequest);
+    }
+}
@@ -0,0 +1,174 @@
+//! Glicko-2 ratings of accounts.
+//!
+//! Every rated game is a rating period of its own, so ratings change right after the game
+//! instead of at fixed intervals. See <http://www.glicko.net/glicko/glicko2.pdf>.
+use std::f64::consts::PI;
+
+use crate::protocol::RatingView;
+
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+/// constrains the change of volatility, the paper suggests 0.3 to 1.2
+const TAU: f64 = 0.5;
+/// factor between the Glicko and Glicko-2 scale
+const SCALE: f64 = 173.7178;
+/// precision of the volatility iteration
+const EPSILON: f64 = 0.000_001;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rating {
+    pub rating: f64,
+    /// rating deviation, how uncertain the rating is
+    pub deviation: f64,
+    /// how much the rating is expected to fluctuate
+    pub volatility: f64,
+}
+impl Default for Rating {
+    fn default() -> Rating {
+        Rating {
+            rating: DEFAULT_RATING,
+            deviation: DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+}
+impl Rating {
+    /// the rating after a game against opponent, score is 1 for a win, 0.5 for a draw and
+    /// 0 for a loss
+    pub fn update(&self, opponent: &Rating, score: f64) -> Rating {
+        let mu = (self.rating - DEFAULT_RATING) / SCALE;
+        let phi = self.deviation / SCALE;
+        let mu_j = (opponent.rating - DEFAULT_RATING) / SCALE;
+        let phi_j = opponent.deviation / SCALE;
+
+        let g = 1.0 / (1.0 + 3.0 * phi_j * phi_j / (PI * PI)).sqrt();
+        let expected = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+        let v = 1.0 / (g * g * expected * (1.0 - expected));
+        let delta = v * g * (score - expected);
+
+        let sigma = self.volatility(phi, v, delta);
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let mu = mu + phi * phi * g * (score - expected);
+        Rating {
+            rating: mu * SCALE + DEFAULT_RATING,
+            deviation: (phi * SCALE).min(DEFAULT_DEVIATION),
+            volatility: sigma,
+        }
+    }
+
+    /// new volatility, by the Illinois algorithm of step 5 of the paper
+    fn volatility(&self, phi: f64, v: f64, delta: f64) -> f64 {
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * d * d) - (x - a) / (TAU * TAU)
+        };
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+        while (big_b - big_a).abs() > EPSILON {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        (big_a / 2.0).exp()
+    }
+
+    /// rating and deviation rounded for clients
+    pub fn view(&self) -> RatingView {
+        RatingView {
+            rating: self.rating.round() as i32,
+            deviation: self.deviation.round() as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, deviation: f64) -> Rating {
+        Rating {
+            rating,
+            deviation,
+            volatility: DEFAULT_VOLATILITY,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 0.1,
+            "{} is not about {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn win_between_new_players() {
+        let winner = Rating::default().update(&Rating::default(), 1.0);
+        assert_close(winner.rating, 1662.3);
+        assert_close(winner.deviation, 290.3);
+        assert!((winner.volatility - DEFAULT_VOLATILITY).abs() < 0.000_1);
+    }
+
+    #[test]
+    fn loss_mirrors_win() {
+        let loser = Rating::default().update(&Rating::default(), 0.0);
+        assert_close(loser.rating, 1337.7);
+        assert_close(loser.deviation, 290.3);
+    }
+
+    #[test]
+    fn draw_between_equals_only_narrows_the_deviation() {
+        let drawn = rating(1600.0, 100.0).update(&rating(1600.0, 100.0), 0.5);
+        assert_close(drawn.rating, 1600.0);
+        assert!(drawn.deviation < 100.0);
+    }
+
+    #[test]
+    fn upset_moves_more_than_expected_win() {
+        let favorite = rating(1800.0, 80.0);
+        let underdog = rating(1400.0, 80.0);
+        let expected = favorite.update(&underdog, 1.0).rating - favorite.rating;
+        let upset = underdog.update(&favorite, 1.0).rating - underdog.rating;
+        assert!(expected > 0.0 && upset > 4.0 * expected);
+        // losing as the favorite costs what the upset earned
+        assert_close(
+            favorite.update(&underdog, 0.0).rating - favorite.rating,
+            -upset,
+        );
+    }
+
+    #[test]
+    fn deviation_stays_within_default() {
+        let uncertain = rating(1500.0, DEFAULT_DEVIATION);
+        let updated = uncertain.update(&rating(3000.0, 30.0), 0.0);
+        assert!(updated.deviation <= DEFAULT_DEVIATION);
+    }
+
+    #[test]
+    fn view_rounds() {
+        let view = rating(1662.3, 290.5).view();
+        assert_eq!((view.rating, view.deviation), (1662, 291));
+    }
+}
@@ -0,0 +1,540 @@
+//! Task of a room.
+//!
+//! Every room runs in its own task owning its [`Room`] and the senders of its members, so
+//! rooms never wait for each other and events are fanned out by the room alone, in seq order.
+//! The task also watches the clock of the side to move and ends the game when it runs out,
+//! and holds the seat of a player who lost connection for a grace period, keeping the events
+//! it misses till it resumes on a new connection. A bot seated in the room thinks on the
+//! [`BotPool`] and its moves come back to the task like those of any player.
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::bot::{BotPool, Engine, DEFAULT_LEVEL, MAX_LEVEL, MIN_LEVEL};
+use crate::error::Error;
+use crate::gomoku_ol::{Board, BotTurn, Room, BOT_ID};
+use crate::lobby::LobbyHandle;
+use crate::protocol::{Event, Request};
+use crate::{ClientSender, Reply};
+
+/// message to the task of a room
+enum RoomMsg {
+    /// enter the room, events of the room are sent to sender from now on
+    Enter {
+        player_id: i32,
+        nickname: String,
+        sender: ClientSender,
+        reply: Reply,
+    },
+    Request {
+        player_id: i32,
+        request: Request,
+        reply: Reply,
+    },
+    /// the connection behind sender dropped, a seat is held for the grace period
+    Leave {
+        player_id: i32,
+        sender: ClientSender,
+    },
+    /// the player resumed on the connection of sender
+    Rejoin {
+        player_id: i32,
+        sender: ClientSender,
+        reply: Reply,
+    },
+    /// a member changed its nickname
+    Rename { player_id: i32, nickname: String },
+}
+
+/// handle to talk to the task of a room
+#[derive(Clone)]
+pub struct RoomHandle {
+    sender: mpsc::UnboundedSender<RoomMsg>,
+}
+impl RoomHandle {
+    /// start the task of room with the senders of players already in it, it reports changes
+    /// of the room to lobby, holds the seat of a player who lost connection for grace_period
+    /// and has bots search on bots
+    pub fn spawn(
+        room: Room,
+        members: HashMap<i32, ClientSender>,
+        lobby: LobbyHandle,
+        grace_period: Duration,
+        bots: BotPool,
+    ) -> RoomHandle {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let actor = RoomActor {
+            room,
+            members,
+            missed: HashMap::new(),
+            lobby,
+            grace_period,
+            bots,
+            bot: None,
+        };
+        tokio::spawn(actor.run(receiver));
+        RoomHandle { sender }
+    }
+
+    /// enter the room, return its state
+    pub async fn enter(
+        &self,
+        player_id: i32,
+        nickname: String,
+        sender: ClientSender,
+    ) -> Result<Value, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(RoomMsg::Enter {
+            player_id,
+            nickname,
+            sender,
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// handle a request about the room
+    pub async fn request(&self, player_id: i32, request: Request) -> Result<Value, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(RoomMsg::Request {
+            player_id,
+            request,
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    /// the connection behind sender dropped, leave the room without waiting
+    pub fn leave(&self, player_id: i32, sender: ClientSender) {
+        self.send(RoomMsg::Leave { player_id, sender })
+            .unwrap_or_default();
+    }
+
+    /// send events of the room to sender from now on, starting with those the player
+    /// missed while away. Only for players in the room
+    pub async fn rejoin(&self, player_id: i32, sender: ClientSender) -> Result<Value, Error> {
+        let (reply, result) = oneshot::channel();
+        self.send(RoomMsg::Rejoin {
+            player_id,
+            sender,
+            reply,
+        })?;
+        result.await.unwrap_or_else(|_| Err(Error::internal()))
+    }
+
+    pub fn rename(&self, player_id: i32, nickname: &str) {
+        self.send(RoomMsg::Rename {
+            player_id,
+            nickname: nickname.to_string(),
+        })
+        .unwrap_or_default();
+    }
+
+    fn send(&self, msg: RoomMsg) -> Result<(), Error> {
+        self.sender.send(msg).map_err(|_| Error::internal())
+    }
+}
+
+struct RoomActor {
+    room: Room,
+    /// sender to the connection of each player in the room
+    members: HashMap<i32, ClientSender>,
+    /// events of the room since each player whose seat is held lost connection
+    missed: HashMap<i32, Vec<String>>,
+    lobby: LobbyHandle,
+    grace_period: Duration,
+    bots: BotPool,
+    bot: Option<Bot>,
+}
+
+/// the bot seated in a room
+struct Bot {
+    engine: Engine,
+    level: u8,
+    /// a search is running, its move is sent back to the task
+    thinking: bool,
+}
+
+/// move the bot found for board, None if there was none
+struct BotMove {
+    board: Board,
+    pos: Option<(usize, usize)>,
+}
+
+impl RoomActor {
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<RoomMsg>) {
+        let (bot_sender, mut bot_receiver) = mpsc::unbounded_channel();
+        loop {
+            let was_started = self.room.has_started();
+            let was_over = self.room.is_over();
+            // seats may be left by the time the game is over
+            let players = self.room.players_by_color();
+            let mut events = Vec::new();
+            tokio::select! {
+                msg = receiver.recv() => match msg {
+                    Some(msg) => self.handle_msg(msg, &mut events),
+                    None => break,
+                },
+                _ = sleep_until(self.room.deadline()) => self.expire(&mut events),
+                Some(bot_move) = bot_receiver.recv() => self.bot_moved(bot_move, &mut events),
+            }
+            self.wake_bot(&bot_sender, &mut events);
+            if !was_started && self.room.has_started() {
+                self.lobby.game_started(self.room.id(), self.room.seated());
+            }
+            // the server decides how the game ends, clients just follow
+            if !was_over {
+                events.extend(self.room.game_over().map(Event::GameOver));
+                if let Some(record) = players.and_then(|v| self.room.record(v)) {
+                    self.lobby.game_finished(record);
+                }
+            }
+            if !events.is_empty() {
+                self.broadcast(&events);
+                self.lobby.room_changed(self.room.info());
+            }
+        }
+    }
+
+    fn handle_msg(&mut self, msg: RoomMsg, events: &mut Vec<Event>) {
+        match msg {
+            RoomMsg::Enter {
+                player_id,
+                nickname,
+                sender,
+                reply,
+            } => {
+                let room_id = self.room.id();
+                let reconnected = self.room.reconnect(player_id);
+                let room_state = self.room.enter(player_id, nickname);
+                // the state is newer than what was missed
+                self.missed.remove(&player_id);
+                self.members.insert(player_id, sender);
+                events.push(if reconnected {
+                    Event::OpponentReconnected { room_id, player_id }
+                } else {
+                    Event::EnterRoom {
+                        room_id,
+                        player_id,
+                        player_type: self.room.type_of_player(player_id).unwrap_or_default(),
+                        nickname: self.room.nickname(player_id),
+                    }
+                });
+                // ignore failure, the player is gone already
+                reply.send(Ok(json!(room_state))).unwrap_or_default();
+            }
+            RoomMsg::Request {
+                player_id,
+                request,
+                reply,
+            } => {
+                let result = self.handle_request(player_id, request, events);
+                reply.send(result).unwrap_or_default();
+            }
+            RoomMsg::Leave { player_id, sender } => {
+                // the player has resumed on another connection already
+                if !self
+                    .members
+                    .get(&player_id)
+                    .is_some_and(|v| v.same_channel(&sender))
+                {
+                    return;
+                }
+                let deadline = Instant::now() + self.grace_period;
+                if !self.grace_period.is_zero() && self.room.disconnect(player_id, deadline) {
+                    self.members.remove(&player_id);
+                    self.missed.insert(player_id, Vec::new());
+                    events.push(Event::OpponentDisconnected {
+                        room_id: self.room.id(),
+                        player_id,
+                        grace_ms: self.grace_period.as_millis() as u64,
+                    });
+                } else {
+                    // ignore failure, the player has left already
+                    self.exit(player_id, events).unwrap_or_default();
+                }
+            }
+            RoomMsg::Rejoin {
+                player_id,
+                sender,
+                reply,
+            } => {
+                let result = self.rejoin(player_id, sender, events);
+                reply.send(result).unwrap_or_default();
+            }
+            RoomMsg::Rename {
+                player_id,
+                nickname,
+            } => {
+                // ignore failure, the player has left already
+                events.extend(self.room.rename(player_id, nickname).ok());
+            }
+        }
+    }
+
+    fn rejoin(
+        &mut self,
+        player_id: i32,
+        sender: ClientSender,
+        events: &mut Vec<Event>,
+    ) -> Result<Value, Error> {
+        self.room.type_of_player(player_id)?;
+        if self.room.reconnect(player_id) {
+            events.push(Event::OpponentReconnected {
+                room_id: self.room.id(),
+                player_id,
+            });
+        }
+        for msg in self.missed.remove(&player_id).unwrap_or_default() {
+            // ignore failure, the connection is closing
+            sender.send(msg).unwrap_or_default();
+        }
+        self.members.insert(player_id, sender);
+        Ok(json!({}))
+    }
+
+    /// end the game if the clock ran out and give up seats held for too long
+    fn expire(&mut self, events: &mut Vec<Event>) {
+        self.room.check_timeout();
+        for event in self.room.expire_disconnected(Instant::now()) {
+            if let Event::ExitRoom { player_id, .. } = event {
+                self.members.remove(&player_id);
+                self.missed.remove(&player_id);
+            }
+            events.push(event);
+        }
+    }
+
+    /// answer a takeback request or start a search if it is up to the bot
+    fn wake_bot(&mut self, bot_moves: &mpsc::UnboundedSender<BotMove>, events: &mut Vec<Event>) {
+        let (engine, level) = match &self.bot {
+            Some(bot) if !bot.thinking => (bot.engine, bot.level),
+            _ => return,
+        };
+        match self.room.bot_turn() {
+            Some(BotTurn::AnswerUndo) => {
+                let request = Request::AnswerUnput {
+                    room_id: self.room.id(),
+                    accept: true,
+                };
+                // ignore failure, the request was withdrawn by a move meanwhile
+                self.handle_request(BOT_ID, request, events)
+                    .unwrap_or_default();
+            }
+            Some(BotTurn::AnswerReset) => {
+                let request = Request::ResetGame {
+                    room_id: self.room.id(),
+                };
+                // ignore failure, the request was withdrawn by a move meanwhile
+                self.handle_request(BOT_ID, request, events)
+                    .unwrap_or_default();
+            }
+            Some(BotTurn::Move(position)) => {
+                self.bot.as_mut().unwrap().thinking = true;
+                let bot_moves = bot_moves.clone();
+                let board = position.board.clone();
+                self.bots.search(position, engine, level, move |pos| {
+                    // ignore failure, the room is gone
+                    bot_moves.send(BotMove { board, pos }).unwrap_or_default();
+                });
+            }
+            None => {}
+        }
+    }
+
+    /// play the move of the bot, unless the game went on without it. The bot resigns if it
+    /// found no move or its move is rejected, searching the same board again would not help
+    fn bot_moved(&mut self, bot_move: BotMove, events: &mut Vec<Event>) {
+        if let Some(bot) = self.bot.as_mut() {
+            bot.thinking = false;
+        }
+        let current = match self.room.bot_turn() {
+            Some(BotTurn::Move(position)) => position.board == bot_move.board,
+            _ => false,
+        };
+        if !current {
+            return;
+        }
+        let room_id = self.room.id();
+        let played = bot_move.pos.is_some_and(|(row_i, col_j)| {
+            let request = Request::PutPiece {
+                room_id,
+                row_i,
+                col_j,
+            };
+            self.handle_request(BOT_ID, request, events).is_ok()
+        });
+        if !played {
+            // ignore failure, the clock ran out meanwhile
+            self.handle_request(BOT_ID, Request::Resign { room_id }, events)
+                .unwrap_or_default();
+        }
+    }
+
+    fn exit(&mut self, player_id: i32, events: &mut Vec<Event>) -> Result<(), Error> {
+        events.push(self.room.exit(player_id)?);
+        self.members.remove(&player_id);
+        Ok(())
+    }
+
+    /// handle request of player_id, events are sent to every member afterwards
+    fn handle_request(
+        &mut self,
+        player_id: i32,
+        request: Request,
+        events: &mut Vec<Event>,
+    ) -> Result<Value, Error> {
+        let room_id = self.room.id();
+        match request {
+            Request::ExitRoom { .. } => {
+                self.exit(player_id, events)?;
+                Ok(json!({}))
+            }
+            Request::ResetGame { .. } => {
+                events.push(if self.room.reset_game(player_id)? {
+                    Event::Reset { room_id }
+                } else {
+                    Event::ResetRequest { room_id, player_id }
+                });
+                Ok(json!({}))
+            }
+            Request::SyncRoom { .. } => Ok(json!(self.room.room_state(player_id)?)),
+            Request::PutPiece { row_i, col_j, .. } => {
+                let piece_type = self.room.put_piece(player_id, row_i, col_j)?;
+                events.push(Event::PutPiece {
+                    room_id,
+                    row_i,
+                    col_j,
+                    piece_type,
+                    clocks: self.room.clocks(),
+                });
+                Ok(json!({}))
+            }
+            Request::UnputPiece { .. } => {
+                self.room.request_undo(player_id)?;
+                events.push(Event::UnputRequest { room_id, player_id });
+                Ok(json!({}))
+            }
+            Request::AnswerUnput { accept, .. } => {
+                let undone = self.room.answer_undo(player_id, accept)?;
+                events.push(if undone.is_empty() {
+                    Event::UnputDeclined { room_id, player_id }
+                } else {
+                    Event::UnputPiece {
+                        room_id,
+                        pieces: undone.iter().map(|v| (v.row_i, v.col_j)).collect(),
+                    }
+                });
+                Ok(json!({}))
+            }
+            Request::Resign { .. } => {
+                self.room.resign(player_id)?;
+                Ok(json!({}))
+            }
+            Request::OfferDraw { .. } => {
+                // offering back agrees to the draw, which ends the game
+                if !self.room.offer_draw(player_id)? {
+                    events.push(Event::DrawOffer { room_id, player_id });
+                }
+                Ok(json!({}))
+            }
+            Request::AcceptDraw { .. } => {
+                self.room.accept_draw(player_id)?;
+                Ok(json!({}))
+            }
+            Request::ForbiddenPoints { .. } => Ok(json!({
+                "points": self.room.forbidden_points(player_id)?
+            })),
+            Request::PlaceOpening {
+                stones,
+                fifth_count,
+                ..
+            } => {
+                let placed = self.room.place_opening(player_id, &stones, fifth_count)?;
+                events.push(Event::PlaceOpening {
+                    room_id,
+                    player_id,
+                    stones: placed
+                        .iter()
+                        .map(|v| (v.row_i, v.col_j, v.piece_type))
+                        .collect(),
+                    opening: self.room.opening(),
+                });
+                Ok(json!({}))
+            }
+            Request::ChooseColor { piece_type, .. } => {
+                self.room.choose_color(player_id, piece_type)?;
+                events.push(Event::ChooseColor {
+                    room_id,
+                    player_id,
+                    piece_type,
+                    opening: self.room.opening(),
+                });
+                Ok(json!({}))
+            }
+            Request::ProposeFifth { points, .. } => {
+                self.room.propose_fifth(player_id, &points)?;
+                events.push(Event::ProposeFifth {
+                    room_id,
+                    player_id,
+                    points,
+                    opening: self.room.opening(),
+                });
+                Ok(json!({}))
+            }
+            Request::ChooseFifth { row_i, col_j, .. } => {
+                self.room.choose_fifth(player_id, (row_i, col_j))?;
+                events.push(Event::ChooseFifth {
+                    room_id,
+                    player_id,
+                    row_i,
+                    col_j,
+                    opening: self.room.opening(),
+                });
+                Ok(json!({}))
+            }
+            Request::AddBot { engine, level, .. } => {
+                let level = level.unwrap_or(DEFAULT_LEVEL);
+                if !(MIN_LEVEL..=MAX_LEVEL).contains(&level) {
+                    return Err(Error::bad_request(&format!(
+                        "level must be between {} and {}",
+                        MIN_LEVEL, MAX_LEVEL
+                    )));
+                }
+                events.push(self.room.add_bot(player_id, engine.nickname(level))?);
+                self.bot = Some(Bot {
+                    engine,
+                    level,
+                    thinking: false,
+                });
+                Ok(json!({}))
+            }
+            // entering goes through RoomHandle::enter, resuming through RoomHandle::rejoin,
+            // the rest belongs to the lobby
+            _ => Err(Error::internal()),
+        }
+    }
+
+    /// number events and send them to every member
+    fn broadcast(&mut self, events: &[Event]) {
+        for event in events {
+            let msg = event.to_json(self.room.next_event_seq());
+            for sender in self.members.values() {
+                // ignore failure, the connection is closing
+                sender.send(msg.clone()).unwrap_or_default();
+            }
+            for missed in self.missed.values_mut() {
+                missed.push(msg.clone());
+            }
+        }
+    }
+}
+
+/// wait till deadline, forever if there is none
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
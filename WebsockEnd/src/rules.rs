@@ -0,0 +1,349 @@
+//! Rule sets deciding which line wins and which moves are forbidden.
+use serde::{Deserialize, Serialize};
+
+use crate::gomoku_ol::{Board, MoveError, PieceType};
+
+/// row/col steps of the four line directions: horizontal, vertical and two diagonals
+pub(crate) const DIRECTIONS: [(i32, i32); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleSet {
+    /// win_length or more in a row wins
+    #[default]
+    Freestyle,
+    /// exactly win_length in a row wins, overlines do not count for either side
+    Standard,
+    /// win_length or more in a row wins, unless the opponent blocks both ends
+    Caro,
+    /// exactly five wins for black, five or more for white. Black can not make
+    /// double-threes, double-fours or overlines
+    Renju,
+}
+impl RuleSet {
+    /// the win length the rule set is played with, None if any is fine
+    pub fn win_length(self) -> Option<usize> {
+        match self {
+            RuleSet::Renju => Some(5),
+            _ => None,
+        }
+    }
+
+    /// the line through pos that wins for the piece at pos, sorted
+    pub fn winning_line(
+        self,
+        board: &Board,
+        pos: (usize, usize),
+        win_length: usize,
+    ) -> Option<Vec<(usize, usize)>> {
+        let piece_type = board.get(pos.0, pos.1);
+        if piece_type == PieceType::EMPTY {
+            return None;
+        }
+        for &dir in DIRECTIONS.iter() {
+            let mut line = board.run(pos, dir);
+            let wins = match self {
+                RuleSet::Standard => line.len() == win_length,
+                RuleSet::Renju if piece_type == PieceType::BLACK => line.len() == win_length,
+                RuleSet::Caro => line.len() >= win_length && !is_blocked(board, &line, dir),
+                _ => line.len() >= win_length,
+            };
+            if wins {
+                line.sort_unstable();
+                return Some(line);
+            }
+        }
+        None
+    }
+
+    /// why piece_type can not be put on the empty point pos, None if it can.
+    /// The board is only borrowed mutably to try moves, it is left as it was
+    pub fn forbidden(
+        self,
+        board: &mut Board,
+        pos: (usize, usize),
+        piece_type: PieceType,
+    ) -> Option<MoveError> {
+        match self {
+            RuleSet::Renju if piece_type == PieceType::BLACK => renju_forbidden(board, pos),
+            _ => None,
+        }
+    }
+
+    /// every empty point piece_type can not be put on
+    pub fn forbidden_points(self, board: &Board, piece_type: PieceType) -> Vec<(usize, usize)> {
+        if self != RuleSet::Renju || piece_type != PieceType::BLACK {
+            return Vec::new();
+        }
+        let mut board = board.clone();
+        let empty_points = board
+            .positions()
+            .filter(|&(row_i, col_j)| board.get(row_i, col_j) == PieceType::EMPTY)
+            .collect::<Vec<(usize, usize)>>();
+        empty_points
+            .into_iter()
+            .filter(|&pos| renju_forbidden(&mut board, pos).is_some())
+            .collect()
+    }
+}
+
+/// whether the opponent has pieces right beyond both ends of line, the board edge does not block
+fn is_blocked(board: &Board, line: &[(usize, usize)], dir: (i32, i32)) -> bool {
+    let piece_type = board.get(line[0].0, line[0].1);
+    let is_opponent = |pos: Option<(usize, usize)>| {
+        pos.map(|v| board.get(v.0, v.1))
+            .is_some_and(|v| v != PieceType::EMPTY && v != piece_type)
+    };
+    is_opponent(board.step(line[0], dir, -1))
+        && is_opponent(board.step(line[line.len() - 1], dir, 1))
+}
+
+/// why black can not be put on the empty point pos in renju. A move making exactly five
+/// is never forbidden
+fn renju_forbidden(board: &mut Board, pos: (usize, usize)) -> Option<MoveError> {
+    if board.get(pos.0, pos.1) != PieceType::EMPTY {
+        return None;
+    }
+    board.set(pos.0, pos.1, PieceType::BLACK);
+    let result = renju_forbidden_placed(board, pos);
+    board.set(pos.0, pos.1, PieceType::EMPTY);
+    result
+}
+
+/// like renju_forbidden, with black already put on pos
+fn renju_forbidden_placed(board: &mut Board, pos: (usize, usize)) -> Option<MoveError> {
+    let run_lens = DIRECTIONS
+        .iter()
+        .map(|&dir| board.run(pos, dir).len())
+        .collect::<Vec<usize>>();
+    if run_lens.contains(&5) {
+        return None;
+    }
+    if run_lens.iter().any(|&v| v > 5) {
+        return Some(MoveError::Overline);
+    }
+    let fours: usize = DIRECTIONS.iter().map(|&dir| fours(board, pos, dir)).sum();
+    if fours >= 2 {
+        return Some(MoveError::DoubleFour);
+    }
+    let open_threes = DIRECTIONS
+        .iter()
+        .filter(|&&dir| is_open_three(board, pos, dir))
+        .count();
+    if open_threes >= 2 {
+        return Some(MoveError::DoubleThree);
+    }
+    None
+}
+
+/// number of fours of black through pos in direction dir, a four being four stones that
+/// one more makes exactly five. An open four with two ways to five is still one four
+fn fours(board: &mut Board, pos: (usize, usize), dir: (i32, i32)) -> usize {
+    let mut fours: Vec<Vec<(usize, usize)>> = Vec::new();
+    for steps in (-4..=4).filter(|&v| v != 0) {
+        let next = match board.step(pos, dir, steps) {
+            Some(v) if board.get(v.0, v.1) == PieceType::EMPTY => v,
+            _ => continue,
+        };
+        board.set(next.0, next.1, PieceType::BLACK);
+        let mut line = board.run(next, dir);
+        board.set(next.0, next.1, PieceType::EMPTY);
+        if line.len() == 5 && line.contains(&pos) {
+            line.retain(|&v| v != next);
+            line.sort_unstable();
+            if !fours.contains(&line) {
+                fours.push(line);
+            }
+        }
+    }
+    fours.len()
+}
+
+/// whether black at pos makes an open three in direction dir: a three that one more
+/// stone, on a point black is allowed to play, turns into an open four
+fn is_open_three(board: &mut Board, pos: (usize, usize), dir: (i32, i32)) -> bool {
+    for steps in (-4..=4).filter(|&v| v != 0) {
+        let next = match board.step(pos, dir, steps) {
+            Some(v) if board.get(v.0, v.1) == PieceType::EMPTY => v,
+            _ => continue,
+        };
+        board.set(next.0, next.1, PieceType::BLACK);
+        let open_four = is_open_four(board, pos, next, dir);
+        board.set(next.0, next.1, PieceType::EMPTY);
+        if open_four && renju_forbidden(board, next).is_none() {
+            return true;
+        }
+    }
+    false
+}
+
+/// whether the black line through pos in direction dir, with next just added to it, is four
+/// stones with both ends open to exactly five
+fn is_open_four(board: &Board, pos: (usize, usize), next: (usize, usize), dir: (i32, i32)) -> bool {
+    let line = board.run(pos, dir);
+    if line.len() != 4 || !line.contains(&next) {
+        return false;
+    }
+    let is = |pos: Option<(usize, usize)>, piece_type: PieceType| {
+        pos.is_some_and(|v| board.get(v.0, v.1) == piece_type)
+    };
+    let (first, last) = (line[0], line[3]);
+    // the point beyond each end has to be empty and the one after it not black, or it
+    // would be an overline
+    is(board.step(first, dir, -1), PieceType::EMPTY)
+        && is(board.step(last, dir, 1), PieceType::EMPTY)
+        && !is(board.step(first, dir, -2), PieceType::BLACK)
+        && !is(board.step(last, dir, 2), PieceType::BLACK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a 15x15 board with black and white stones
+    fn board(black: &[(usize, usize)], white: &[(usize, usize)]) -> Board {
+        let mut board = Board::new(15, 15);
+        for &(row_i, col_j) in black {
+            board.set(row_i, col_j, PieceType::BLACK);
+        }
+        for &(row_i, col_j) in white {
+            board.set(row_i, col_j, PieceType::WHITE);
+        }
+        board
+    }
+
+    fn renju(board: &mut Board, pos: (usize, usize)) -> Option<MoveError> {
+        RuleSet::Renju.forbidden(board, pos, PieceType::BLACK)
+    }
+
+    #[test]
+    fn crossing_double_three() {
+        let mut board = board(&[(7, 6), (7, 8), (6, 7), (8, 7)], &[]);
+        assert_eq!(renju(&mut board, (7, 7)), Some(MoveError::DoubleThree));
+    }
+
+    #[test]
+    fn split_double_three() {
+        let mut board = board(&[(7, 5), (7, 8), (5, 6), (6, 6)], &[]);
+        assert_eq!(renju(&mut board, (7, 6)), Some(MoveError::DoubleThree));
+    }
+
+    #[test]
+    fn double_four_on_one_line() {
+        let mut board = board(&[(7, 1), (7, 3), (7, 5), (7, 7)], &[]);
+        assert_eq!(renju(&mut board, (7, 4)), Some(MoveError::DoubleFour));
+    }
+
+    #[test]
+    fn crossing_double_four() {
+        let mut board = board(&[(7, 4), (7, 5), (7, 6), (4, 7), (5, 7), (6, 7)], &[]);
+        assert_eq!(renju(&mut board, (7, 7)), Some(MoveError::DoubleFour));
+    }
+
+    #[test]
+    fn four_three_is_allowed() {
+        let mut board = board(&[(7, 4), (7, 5), (7, 6), (5, 7), (6, 7)], &[]);
+        assert!(renju(&mut board, (7, 7)).is_none());
+    }
+
+    #[test]
+    fn overline_is_forbidden() {
+        let mut board = board(&[(7, 2), (7, 3), (7, 4), (7, 6), (7, 7)], &[]);
+        assert_eq!(renju(&mut board, (7, 5)), Some(MoveError::Overline));
+    }
+
+    #[test]
+    fn five_with_overline_is_allowed() {
+        let black = [
+            (7, 3),
+            (7, 4),
+            (7, 5),
+            (7, 6),
+            (4, 7),
+            (5, 7),
+            (6, 7),
+            (8, 7),
+            (9, 7),
+        ];
+        let mut board = board(&black, &[]);
+        assert!(renju(&mut board, (7, 7)).is_none());
+    }
+
+    #[test]
+    fn three_blocked_at_one_end_is_not_open() {
+        let mut board = board(&[(7, 5), (7, 6), (5, 7), (6, 7)], &[(7, 4)]);
+        board.set(7, 7, PieceType::BLACK);
+        assert!(!is_open_three(&mut board, (7, 7), (0, 1)));
+        assert!(is_open_three(&mut board, (7, 7), (1, 0)));
+        board.set(7, 7, PieceType::EMPTY);
+        assert!(renju(&mut board, (7, 7)).is_none());
+    }
+
+    #[test]
+    fn white_is_never_forbidden() {
+        let mut board = board(&[], &[(7, 6), (7, 8), (6, 7), (8, 7)]);
+        assert!(RuleSet::Renju
+            .forbidden(&mut board, (7, 7), PieceType::WHITE)
+            .is_none());
+        assert!(RuleSet::Freestyle
+            .forbidden(&mut board, (7, 7), PieceType::BLACK)
+            .is_none());
+    }
+
+    /// a run of len black stones along row 7 from column col_j
+    fn row(col_j: usize, len: usize) -> Vec<(usize, usize)> {
+        (col_j..col_j + len).map(|v| (7, v)).collect()
+    }
+
+    #[test]
+    fn standard_five_wins() {
+        let board = board(&row(3, 5), &[]);
+        assert_eq!(
+            RuleSet::Standard.winning_line(&board, (7, 5), 5),
+            Some(row(3, 5))
+        );
+    }
+
+    #[test]
+    fn standard_overline_does_not_win() {
+        let board = board(&row(3, 6), &[]);
+        assert!(RuleSet::Standard.winning_line(&board, (7, 5), 5).is_none());
+        assert_eq!(
+            RuleSet::Freestyle.winning_line(&board, (7, 5), 5),
+            Some(row(3, 6))
+        );
+    }
+
+    #[test]
+    fn caro_five_blocked_at_both_ends_does_not_win() {
+        let board = board(&row(3, 5), &[(7, 2), (7, 8)]);
+        assert!(RuleSet::Caro.winning_line(&board, (7, 5), 5).is_none());
+    }
+
+    #[test]
+    fn caro_five_blocked_at_one_end_wins() {
+        let board = board(&row(3, 5), &[(7, 2)]);
+        assert_eq!(
+            RuleSet::Caro.winning_line(&board, (7, 5), 5),
+            Some(row(3, 5))
+        );
+    }
+
+    #[test]
+    fn caro_five_against_the_edge_wins() {
+        let board = board(&row(0, 5), &[(7, 5)]);
+        assert_eq!(
+            RuleSet::Caro.winning_line(&board, (7, 2), 5),
+            Some(row(0, 5))
+        );
+    }
+
+    #[test]
+    fn caro_overline_wins() {
+        let board = board(&row(3, 6), &[(7, 2)]);
+        assert_eq!(
+            RuleSet::Caro.winning_line(&board, (7, 5), 5),
+            Some(row(3, 6))
+        );
+    }
+}
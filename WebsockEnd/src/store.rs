@@ -0,0 +1,608 @@
+//! Accounts and finished games, kept in a SQLite file so they survive restarts.
+//!
+//! Passwords are stored as salted argon2 hashes. Hashing is slow on purpose, so it is done by
+//! the connection tasks with [`hash_password`] and [`verify_password`], the lobby only reads
+//! and writes rows. The leaderboard and player statistics are read on blocking threads through
+//! a connection of their own, the file is in WAL mode so they do not hold up the lobby.
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, ErrorCode};
+use crate::gomoku_ol::{
+    GameRecord, GameStatus, Stats, DEFAULT_LEADERBOARD_LIMIT, MAX_LEADERBOARD_LIMIT,
+};
+use crate::protocol::{Leaderboard, LeaderboardEntry, PlayerStats};
+use crate::rating::Rating;
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+pub const MAX_PASSWORD_LEN: usize = 128;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS accounts (
+    id INTEGER PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    password_hash TEXT NOT NULL,
+    games INTEGER NOT NULL DEFAULT 0,
+    wins INTEGER NOT NULL DEFAULT 0,
+    losses INTEGER NOT NULL DEFAULT 0,
+    draws INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS games (
+    id INTEGER PRIMARY KEY,
+    -- NULL for guests
+    black INTEGER REFERENCES accounts(id),
+    white INTEGER REFERENCES accounts(id),
+    status TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    moves INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL
+);
+";
+/// changes to SCHEMA, a database has run the first `user_version` of them
+const MIGRATIONS: &[&str] = &[
+    "
+ALTER TABLE accounts ADD COLUMN rating REAL NOT NULL DEFAULT 1500;
+ALTER TABLE accounts ADD COLUMN deviation REAL NOT NULL DEFAULT 350;
+ALTER TABLE accounts ADD COLUMN volatility REAL NOT NULL DEFAULT 0.06;
+-- whether the game changed the ratings of its players
+ALTER TABLE games ADD COLUMN rated INTEGER NOT NULL DEFAULT 0;
+",
+    "
+CREATE INDEX games_black ON games(black);
+CREATE INDEX games_white ON games(white);
+-- kept up to date by add_game so the leaderboard needs no counting
+ALTER TABLE accounts ADD COLUMN rated_games INTEGER NOT NULL DEFAULT 0;
+UPDATE accounts SET rated_games =
+    (SELECT COUNT(*) FROM games WHERE rated AND black = accounts.id)
+    + (SELECT COUNT(*) FROM games WHERE rated AND white = accounts.id);
+CREATE INDEX accounts_leaderboard ON accounts(rating DESC, id) WHERE rated_games > 0;
+",
+];
+
+/// a registered player
+pub struct Account {
+    pub id: i64,
+    pub username: String,
+    pub password_hash: String,
+    pub stats: Stats,
+    pub rating: Rating,
+}
+
+/// the database of accounts and games
+pub struct Store {
+    conn: Connection,
+}
+impl Store {
+    /// open the database at path, creating it and its tables if missing and bringing them
+    /// up to date
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Store> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        migrate(&conn)?;
+        Ok(Store { conn })
+    }
+
+    /// add an account, return its id
+    pub fn create_account(&self, username: &str, password_hash: &str) -> Result<i64, Error> {
+        let result = self.conn.execute(
+            "INSERT INTO accounts (username, password_hash, created_at) VALUES (?1, ?2, ?3)",
+            params![username, password_hash, unix_time()],
+        );
+        match result {
+            Ok(_) => Ok(self.conn.last_insert_rowid()),
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(Error::new(
+                    ErrorCode::NicknameTaken,
+                    &format!("username {} is taken", username),
+                ))
+            }
+            Err(err) => Err(db_error(err)),
+        }
+    }
+
+    /// the account called username, ignoring case
+    pub fn find_account(&self, username: &str) -> Result<Option<Account>, Error> {
+        self.query_account("WHERE username = ?1", username)
+    }
+
+    pub fn account(&self, account_id: i64) -> Result<Option<Account>, Error> {
+        self.query_account("WHERE id = ?1", account_id)
+    }
+
+    fn query_account<P: rusqlite::ToSql>(
+        &self,
+        condition: &str,
+        param: P,
+    ) -> Result<Option<Account>, Error> {
+        let sql = format!(
+            "SELECT id, username, password_hash, games, wins, losses, draws,
+                rating, deviation, volatility FROM accounts {}",
+            condition
+        );
+        self.conn
+            .query_row(&sql, [param], |row| {
+                Ok(Account {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    password_hash: row.get(2)?,
+                    stats: Stats {
+                        games: row.get(3)?,
+                        wins: row.get(4)?,
+                        losses: row.get(5)?,
+                        draws: row.get(6)?,
+                    },
+                    rating: Rating {
+                        rating: row.get(7)?,
+                        deviation: row.get(8)?,
+                        volatility: row.get(9)?,
+                    },
+                })
+            })
+            .optional()
+            .map_err(db_error)
+    }
+
+    pub fn save_account(
+        &self,
+        account_id: i64,
+        stats: &Stats,
+        rating: &Rating,
+    ) -> Result<(), Error> {
+        self.conn
+            .execute(
+                "UPDATE accounts SET games = ?2, wins = ?3, losses = ?4, draws = ?5,
+                    rating = ?6, deviation = ?7, volatility = ?8 WHERE id = ?1",
+                params![
+                    account_id,
+                    stats.games,
+                    stats.wins,
+                    stats.losses,
+                    stats.draws,
+                    rating.rating,
+                    rating.deviation,
+                    rating.volatility
+                ],
+            )
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    /// archive a finished game, black and white are the accounts of the players if any
+    pub fn add_game(
+        &self,
+        record: &GameRecord,
+        black: Option<i64>,
+        white: Option<i64>,
+        rated: bool,
+    ) -> Result<(), Error> {
+        let transaction = self.conn.unchecked_transaction().map_err(db_error)?;
+        transaction
+            .execute(
+                "INSERT INTO games (black, white, status, reason, moves, finished_at, rated)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    black,
+                    white,
+                    name(&record.status),
+                    name(&record.reason),
+                    record.moves,
+                    unix_time(),
+                    rated
+                ],
+            )
+            .map_err(db_error)?;
+        if rated {
+            transaction
+                .execute(
+                    "UPDATE accounts SET rated_games = rated_games + 1 WHERE id IN (?1, ?2)",
+                    params![black, white],
+                )
+                .map_err(db_error)?;
+        }
+        transaction.commit().map_err(db_error)
+    }
+
+    /// accounts with rated games by rating, limit of them after skipping the first offset
+    pub fn leaderboard(&self, offset: u32, limit: Option<u32>) -> Result<Leaderboard, Error> {
+        let limit = limit.unwrap_or(DEFAULT_LEADERBOARD_LIMIT);
+        if !(1..=MAX_LEADERBOARD_LIMIT).contains(&limit) {
+            return Err(Error::bad_request(&format!(
+                "limit must be between 1 and {}",
+                MAX_LEADERBOARD_LIMIT
+            )));
+        }
+        let total = self
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM accounts WHERE rated_games > 0",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(db_error)?;
+        let mut statement = self
+            .conn
+            .prepare(
+                "SELECT username, rating, deviation, rated_games FROM accounts
+                 WHERE rated_games > 0 ORDER BY rating DESC, id LIMIT ?1 OFFSET ?2",
+            )
+            .map_err(db_error)?;
+        let entries = statement
+            .query_map(params![limit, offset], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    Rating {
+                        rating: row.get(1)?,
+                        deviation: row.get(2)?,
+                        ..Rating::default()
+                    },
+                    row.get::<_, u32>(3)?,
+                ))
+            })
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(db_error)?
+            .into_iter()
+            .zip(offset + 1..)
+            .map(|((username, rating, rated_games), rank)| LeaderboardEntry {
+                rank,
+                username,
+                rating: rating.view(),
+                rated_games,
+            })
+            .collect();
+        Ok(Leaderboard { total, entries })
+    }
+
+    /// statistics of the account called username, ignoring case, from its archived games
+    pub fn player_stats(&self, username: &str) -> Result<PlayerStats, Error> {
+        let account = self.find_account(username.trim())?.ok_or_else(|| {
+            Error::new(
+                ErrorCode::NoSuchPlayer,
+                &format!("there is no account called {}", username),
+            )
+        })?;
+        let mut statement = self
+            .conn
+            .prepare(
+                "SELECT black, status, moves, rated FROM games
+                 WHERE black = ?1 OR white = ?1 ORDER BY id",
+            )
+            .map_err(db_error)?;
+        let games = statement
+            .query_map([account.id], |row| {
+                Ok((
+                    row.get::<_, Option<i64>>(0)? == Some(account.id),
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u64>(2)?,
+                    row.get::<_, bool>(3)?,
+                ))
+            })
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(db_error)?;
+        let (black_won, white_won) = (name(&GameStatus::BlackWon), name(&GameStatus::WhiteWon));
+        let mut stats = PlayerStats {
+            username: account.username,
+            rating: account.rating.view(),
+            games: games.len() as u32,
+            rated_games: 0,
+            wins_as_black: 0,
+            wins_as_white: 0,
+            losses: 0,
+            draws: 0,
+            average_moves: 0.0,
+            current_streak: 0,
+            longest_win_streak: 0,
+            longest_loss_streak: 0,
+        };
+        let mut moves = 0;
+        for (as_black, status, game_moves, rated) in games {
+            moves += game_moves;
+            stats.rated_games += rated as u32;
+            let won = if status == black_won {
+                Some(as_black)
+            } else if status == white_won {
+                Some(!as_black)
+            } else {
+                None
+            };
+            match won {
+                Some(true) => {
+                    if as_black {
+                        stats.wins_as_black += 1;
+                    } else {
+                        stats.wins_as_white += 1;
+                    }
+                    stats.current_streak = stats.current_streak.max(0) + 1;
+                    stats.longest_win_streak =
+                        stats.longest_win_streak.max(stats.current_streak as u32);
+                }
+                Some(false) => {
+                    stats.losses += 1;
+                    stats.current_streak = stats.current_streak.min(0) - 1;
+                    stats.longest_loss_streak = stats
+                        .longest_loss_streak
+                        .max(stats.current_streak.unsigned_abs());
+                }
+                None => {
+                    stats.draws += 1;
+                    stats.current_streak = 0;
+                }
+            }
+        }
+        if stats.games > 0 {
+            stats.average_moves = moves as f64 / stats.games as f64;
+        }
+        Ok(stats)
+    }
+}
+
+/// create the tables of conn if missing and run the migrations it has not run yet
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(SCHEMA)?;
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute_batch(&format!(
+            "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+            migration,
+            i + 1
+        ))?;
+    }
+    Ok(())
+}
+
+/// fail unless password has an acceptable length
+pub fn check_password(password: &str) -> Result<(), Error> {
+    let len = password.chars().count();
+    if !(MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&len) {
+        return Err(Error::bad_request(&format!(
+            "password must be {} to {} characters",
+            MIN_PASSWORD_LEN, MAX_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// salted hash of password to store
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|v| v.to_string())
+        .map_err(|err| {
+            eprintln!("hash password failed: {}", err);
+            Error::internal()
+        })
+}
+
+/// whether password is the one password_hash was made of
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// the name a unit enum variant goes over the wire with
+fn name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |v| v.as_secs() as i64)
+}
+
+fn db_error(err: rusqlite::Error) -> Error {
+    eprintln!("database error: {}", err);
+    Error::internal()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gomoku_ol::EndReason;
+
+    fn store() -> Store {
+        Store::open(":memory:").unwrap()
+    }
+
+    fn game(status: GameStatus, moves: usize) -> GameRecord {
+        GameRecord {
+            room_id: 1,
+            black: 1,
+            white: 2,
+            status,
+            reason: EndReason::Five,
+            moves,
+            rated: true,
+        }
+    }
+
+    fn rate(store: &Store, account_id: i64, rating: f64) {
+        let rating = Rating {
+            rating,
+            ..Rating::default()
+        };
+        store
+            .save_account(account_id, &Stats::default(), &rating)
+            .unwrap();
+    }
+
+    #[test]
+    fn player_stats_counts_streaks_and_average_moves() {
+        let store = store();
+        let alice = store.create_account("Alice", "hash").unwrap();
+        let bob = store.create_account("Bob", "hash").unwrap();
+        let (black, white) = (Some(alice), Some(bob));
+        // from the side of Alice: win, win, loss, loss, loss, draw, win
+        store
+            .add_game(&game(GameStatus::BlackWon, 9), black, white, true)
+            .unwrap();
+        store
+            .add_game(&game(GameStatus::WhiteWon, 11), white, black, false)
+            .unwrap();
+        store
+            .add_game(&game(GameStatus::WhiteWon, 20), black, white, true)
+            .unwrap();
+        store
+            .add_game(&game(GameStatus::BlackWon, 30), white, black, true)
+            .unwrap();
+        store
+            .add_game(&game(GameStatus::WhiteWon, 40), black, None, false)
+            .unwrap();
+        store
+            .add_game(&game(GameStatus::Draw, 225), None, black, false)
+            .unwrap();
+        store
+            .add_game(&game(GameStatus::WhiteWon, 15), white, black, true)
+            .unwrap();
+        let stats = store.player_stats(" alice ").unwrap();
+        assert_eq!(stats.username, "Alice");
+        assert_eq!((stats.games, stats.rated_games), (7, 4));
+        assert_eq!((stats.wins_as_black, stats.wins_as_white), (1, 2));
+        assert_eq!((stats.losses, stats.draws), (3, 1));
+        assert_eq!(stats.average_moves, 50.0);
+        assert_eq!(stats.current_streak, 1);
+        assert_eq!(
+            (stats.longest_win_streak, stats.longest_loss_streak),
+            (2, 3)
+        );
+        let stats = store.player_stats("Bob").unwrap();
+        assert_eq!((stats.games, stats.current_streak), (5, -1));
+        assert_eq!(stats.wins_as_black + stats.wins_as_white, 2);
+    }
+
+    #[test]
+    fn player_stats_of_an_account_without_games() {
+        let store = store();
+        store.create_account("Alice", "hash").unwrap();
+        let stats = store.player_stats("Alice").unwrap();
+        assert_eq!((stats.games, stats.current_streak), (0, 0));
+        assert_eq!(stats.average_moves, 0.0);
+        let err = store.player_stats("Carol").err().unwrap();
+        assert_eq!(err.code, ErrorCode::NoSuchPlayer);
+    }
+
+    #[test]
+    fn leaderboard_pages_through_rated_accounts() {
+        let store = store();
+        let ids = ["A", "B", "C", "D", "E"]
+            .iter()
+            .map(|v| store.create_account(v, "hash").unwrap())
+            .collect::<Vec<i64>>();
+        for (&id, rating) in ids.iter().zip([1600.0, 1700.0, 1500.0, 1800.0, 2000.0]) {
+            rate(&store, id, rating);
+        }
+        // E has no rated game and stays off the leaderboard
+        let record = game(GameStatus::Draw, 9);
+        store
+            .add_game(&record, Some(ids[0]), Some(ids[1]), true)
+            .unwrap();
+        store
+            .add_game(&record, Some(ids[2]), Some(ids[3]), true)
+            .unwrap();
+        store.add_game(&record, Some(ids[4]), None, false).unwrap();
+        let page = store.leaderboard(0, None).unwrap();
+        assert_eq!(page.total, 4);
+        let names = page
+            .entries
+            .iter()
+            .map(|v| (v.rank, v.username.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [(1, "D"), (2, "B"), (3, "A"), (4, "C")]);
+        let page = store.leaderboard(1, Some(2)).unwrap();
+        let names = page
+            .entries
+            .iter()
+            .map(|v| (v.rank, v.username.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!((page.total, names), (4, vec![(2, "B"), (3, "A")]));
+        assert!(store.leaderboard(4, None).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn leaderboard_limit_is_bounded() {
+        let store = store();
+        for limit in [0, MAX_LEADERBOARD_LIMIT + 1] {
+            let err = store.leaderboard(0, Some(limit)).err().unwrap();
+            assert_eq!(err.code, ErrorCode::BadRequest);
+        }
+        assert!(store.leaderboard(0, Some(MAX_LEADERBOARD_LIMIT)).is_ok());
+        let ids = (0..DEFAULT_LEADERBOARD_LIMIT + 1)
+            .map(|v| store.create_account(&v.to_string(), "hash").unwrap())
+            .collect::<Vec<i64>>();
+        for pair in ids.chunks(2) {
+            store
+                .add_game(
+                    &game(GameStatus::Draw, 9),
+                    Some(pair[0]),
+                    pair.get(1).copied(),
+                    true,
+                )
+                .unwrap();
+        }
+        let page = store.leaderboard(0, None).unwrap();
+        assert_eq!(page.total, DEFAULT_LEADERBOARD_LIMIT + 1);
+        assert_eq!(page.entries.len(), DEFAULT_LEADERBOARD_LIMIT as usize);
+    }
+
+    #[test]
+    fn migrations_bring_an_old_database_up_to_date() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute_batch(&format!("{} PRAGMA user_version = 1;", MIGRATIONS[0]))
+            .unwrap();
+        conn.execute_batch(
+            "INSERT INTO accounts (id, username, password_hash, created_at, rating)
+                VALUES (1, 'Alice', 'hash', 0, 1600), (2, 'Bob', 'hash', 0, 1400),
+                (3, 'Carol', 'hash', 0, 1500);
+             INSERT INTO games (black, white, status, reason, moves, finished_at, rated)
+                VALUES (1, 2, 'black_won', 'five', 9, 0, 1), (2, 1, 'draw', 'draw_agreed', 9, 0, 1),
+                (3, NULL, 'white_won', 'resign', 9, 0, 0);",
+        )
+        .unwrap();
+        migrate(&conn).unwrap();
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        let store = Store { conn };
+        let page = store.leaderboard(0, None).unwrap();
+        let entries = page
+            .entries
+            .iter()
+            .map(|v| (v.username.as_str(), v.rated_games))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, [("Alice", 2), ("Bob", 2)]);
+        // migrating again changes nothing
+        migrate(&store.conn).unwrap();
+        assert_eq!(store.leaderboard(0, None).unwrap().total, 2);
+    }
+
+    #[test]
+    fn migrations_run_on_the_first_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO accounts (username, password_hash, created_at)
+                VALUES ('Alice', 'hash', 0);",
+        )
+        .unwrap();
+        migrate(&conn).unwrap();
+        let store = Store { conn };
+        let alice = store.find_account("alice").unwrap().unwrap();
+        assert_eq!(alice.rating.rating, Rating::default().rating);
+        assert_eq!(store.leaderboard(0, None).unwrap().total, 0);
+    }
+}
@@ -1,11 +1,32 @@
 
 /* 
-"create_room" - room_name
+"create_room" - room_name, [row_size, col_size, win_length(all or none, default 15, 15, 5), [rule(freestyle/standard/caro/renju)]]
 "room_list" - 
-"enter_room" - player_id, room_id
-"exit_room" - player_id, room_id
+"enter_room" - [player_id(must be your own id)], room_id
+"exit_room" - [player_id(must be your own id)], room_id
 "reset_game" - room_id
-"put_piece" - room_id, row_i, col_j, piece_type(0:Black,1:White)
+"sync_room" - room_id
+"put_piece" - room_id, row_i, col_j, [piece_type(ignored, decided by your seat)]
+"unput_piece" - room_id
+"answer_unput" - room_id, accept(1:Accept,0:Decline)
+"forbidden_points" - room_id
+"place_opening" - room_id, row_i, col_j, [row_i, col_j...], [fifth_count(only with the 4th stone of soosorv8)]
+"choose_color" - room_id, piece_type(0:Black,1:White)
+"propose_fifth" - room_id, row_i, col_j, [row_i, col_j...]
+"choose_fifth" - room_id, row_i, col_j
+"resign" - room_id
+"offer_draw" - room_id
+"accept_draw" - room_id
+"resume" - token(sent with your_id)
+"set_nickname" - nickname
+"profile" - player_id
+"register" - username, password
+"login" - username, password
+"leaderboard" - [offset, limit]
+"player_stats" - username
+"join_queue" - (default settings)
+"leave_queue"
+"add_bot" - room_id, [level(1-5, default 3), [engine(alpha_beta/mcts, default alpha_beta)]]
 */
 
 let ws = new WebSocket("ws://192.168.31.4:8686");